@@ -1,4 +1,5 @@
 use std::{
+    ptr::addr_of,
     sync::atomic::{AtomicBool, Ordering},
     thread,
     time::Duration,
@@ -22,6 +23,6 @@ fn main() {
     }
 
     unsafe {
-        println!("{}", DATA);
+        println!("{}", *addr_of!(DATA));
     }
 }
@@ -1,4 +1,5 @@
 use std::{
+    ptr::{addr_of, addr_of_mut},
     sync::atomic::{AtomicBool, Ordering},
     thread,
 };
@@ -13,7 +14,7 @@ fn f() {
     {
         // Safety: We're holding the exclusive lock, so nothing else is accessing DATA.
         unsafe {
-            DATA.push('!');
+            (*addr_of_mut!(DATA)).push('!');
         }
         LOCKED.store(false, Ordering::Release);
     }
@@ -27,6 +28,7 @@ fn main() {
     });
 
     unsafe {
-        println!("{}, length: {}", DATA, DATA.len());
+        let data = &*addr_of!(DATA);
+        println!("{}, length: {}", data, data.len());
     }
 }
@@ -1,4 +1,5 @@
 use std::{
+    ptr::{addr_of, addr_of_mut},
     sync::atomic::{AtomicBool, Ordering},
     thread,
     time::Duration,
@@ -24,7 +25,7 @@ fn main() {
             println!("Thread a: B has not been set to true, -> accessing S");
             thread::sleep(Duration::from_millis(20));
             unsafe {
-                S.push('!');
+                (*addr_of_mut!(S)).push('!');
             }
         }
     });
@@ -40,7 +41,7 @@ fn main() {
         // Check that the other thread hasn't access S before changing S
         if !A.load(Ordering::SeqCst) {
             println!("Thread b: A has not been set to true, -> accessing S");
-            unsafe { S.push('!') };
+            unsafe { (*addr_of_mut!(S)).push('!') };
         }
     });
     a.join().unwrap();
@@ -54,9 +55,10 @@ fn main() {
     // (There are scenarios in which the first 2 operations are stores, in which no thread accesses S.)
 
     unsafe {
-        if S.is_empty() {
+        let s = &*addr_of!(S);
+        if s.is_empty() {
             println!("No thread accessed S, Ok");
-        } else if S.len() == 1 {
+        } else if s.len() == 1 {
             println!("One thread access S, Ok");
         } else {
             eprintln!("More than 1 thread accessed S -> FAIL!");
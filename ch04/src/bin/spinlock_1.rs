@@ -40,4 +40,10 @@ impl SpinLock {
     }
 }
 
+impl Default for SpinLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 fn main() {}
@@ -0,0 +1,178 @@
+//! A slot for one `Waker`, which one task registers and another thread wakes,
+//! like `AtomicWaker` in the `futures` crate.
+//!
+//! The state works like a tiny lock around the slot: `register` holds it while
+//! replacing the waker, and `wake` while taking it out. If they race, neither
+//! blocks: the one that finds the other holding the lock makes sure the task
+//! is woken anyway.
+
+use std::{
+    cell::UnsafeCell,
+    sync::atomic::{AtomicUsize, Ordering},
+    task::Waker,
+};
+
+/// Nobody is accessing the slot.
+const WAITING: usize = 0;
+/// `register` is replacing the waker.
+const REGISTERING: usize = 1;
+/// `wake` is taking the waker out.
+const WAKING: usize = 2;
+
+pub struct AtomicWaker {
+    /// WAITING, or the REGISTERING and/or WAKING bits
+    state: AtomicUsize,
+    waker: UnsafeCell<Option<Waker>>,
+}
+
+unsafe impl Send for AtomicWaker {}
+unsafe impl Sync for AtomicWaker {}
+
+impl AtomicWaker {
+    pub const fn new() -> Self {
+        Self {
+            state: AtomicUsize::new(WAITING),
+            waker: UnsafeCell::new(None),
+        }
+    }
+
+    /// Store `waker`, to be woken by the next call to `wake`.
+    ///
+    /// Only one task may register at a time (it's meant for the single receiver
+    /// of a channel): concurrent calls to `register` are not supported.
+    pub fn register(&self, waker: &Waker) {
+        // Acquire: see the slot as the last `wake` left it.
+        match self
+            .state
+            .compare_exchange(WAITING, REGISTERING, Ordering::Acquire, Ordering::Acquire)
+            .unwrap_or_else(|s| s)
+        {
+            WAITING => {
+                let slot = unsafe { &mut *self.waker.get() };
+                // Avoid the clone if it's the same waker as last time.
+                if !slot.as_ref().is_some_and(|w| w.will_wake(waker)) {
+                    *slot = Some(waker.clone());
+                }
+                // Release: publish the waker to `wake`.
+                if self
+                    .state
+                    .compare_exchange(REGISTERING, WAITING, Ordering::AcqRel, Ordering::Acquire)
+                    .is_err()
+                {
+                    // `wake` was called while we held the slot, and didn't touch it.
+                    // So wake the waker ourselves.
+                    let waker = slot.take();
+                    self.state.swap(WAITING, Ordering::AcqRel);
+                    if let Some(waker) = waker {
+                        waker.wake();
+                    }
+                }
+            }
+            WAKING => {
+                // `wake` is in progress, and might have taken an older waker.
+                // Wake the new one directly, so the task polls again.
+                waker.wake_by_ref();
+            }
+            _ => {
+                // Another `register` is in progress, which isn't supported.
+                debug_assert!(false, "concurrent AtomicWaker::register");
+            }
+        }
+    }
+
+    /// Wake the registered waker, if any.
+    pub fn wake(&self) {
+        if let Some(waker) = self.take() {
+            waker.wake();
+        }
+    }
+
+    /// Take the registered waker out, if any.
+    pub fn take(&self) -> Option<Waker> {
+        match self.state.fetch_or(WAKING, Ordering::AcqRel) {
+            WAITING => {
+                let waker = unsafe { (*self.waker.get()).take() };
+                // Release: hand the (now empty) slot back to `register`.
+                self.state.fetch_and(!WAKING, Ordering::Release);
+                waker
+            }
+            // `register` holds the slot, and will see our WAKING bit and wake the
+            // new waker itself. Or another `wake` is already taking it out.
+            _ => None,
+        }
+    }
+}
+
+impl Default for AtomicWaker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AtomicWaker;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Wake, Waker},
+        thread,
+    };
+
+    #[derive(Default)]
+    struct CountingWaker(AtomicUsize);
+
+    impl Wake for CountingWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn wake_once() {
+        let count = Arc::new(CountingWaker::default());
+        let waker = Waker::from(count.clone());
+        let w = AtomicWaker::new();
+        w.wake(); // Nothing registered yet
+        w.register(&waker);
+        w.wake();
+        w.wake(); // Taken out by the first wake
+        assert_eq!(count.0.load(Ordering::Relaxed), 1);
+        assert!(w.take().is_none());
+    }
+
+    #[test]
+    fn wakes_latest_waker() {
+        let first = Arc::new(CountingWaker::default());
+        let second = Arc::new(CountingWaker::default());
+        let w = AtomicWaker::new();
+        w.register(&Waker::from(first.clone()));
+        w.register(&Waker::from(second.clone()));
+        w.wake();
+        assert_eq!(first.0.load(Ordering::Relaxed), 0);
+        assert_eq!(second.0.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn no_lost_wakeups() {
+        // Register, then check a flag, while another thread sets the flag, then
+        // wakes. Either we see the flag, or our waker is woken.
+        for _ in 0..1000 {
+            let count = Arc::new(CountingWaker::default());
+            let waker = Waker::from(count.clone());
+            let w = AtomicWaker::new();
+            let flag = AtomicBool::new(false);
+            let saw_flag = thread::scope(|s| {
+                s.spawn(|| {
+                    flag.store(true, Ordering::SeqCst);
+                    w.wake();
+                });
+                w.register(&waker);
+                flag.load(Ordering::SeqCst)
+            });
+            assert!(saw_flag || count.0.load(Ordering::Relaxed) == 1);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! A minimal executor, to run async code (like `Receiver` as a `Future`)
+//! without an external runtime.
+
+use std::{
+    future::Future,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::{self, Thread},
+};
+
+/// Wakes the thread that is blocked in `block_on`.
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Run a future to completion on the current thread, parking it while the
+/// future is pending.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            // If we were woken in the meantime, the unpark token makes park()
+            // return right away. It can also return spuriously: then we just poll again.
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::block_on;
+    use std::{
+        future::poll_fn,
+        sync::atomic::{AtomicBool, Ordering},
+        task::Poll,
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn ready() {
+        assert_eq!(block_on(async { 1 + 2 }), 3);
+    }
+
+    #[test]
+    fn woken_by_another_thread() {
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            let result = block_on(poll_fn(|cx| {
+                if done.load(Ordering::Acquire) {
+                    return Poll::Ready("done");
+                }
+                let waker = cx.waker().clone();
+                let done = &done;
+                s.spawn(move || {
+                    thread::sleep(Duration::from_millis(10));
+                    done.store(true, Ordering::Release);
+                    waker.wake();
+                });
+                Poll::Pending
+            }));
+            assert_eq!(result, "done");
+        });
+    }
+}
@@ -4,11 +4,15 @@ use std::{
     mem::MaybeUninit,
     sync::atomic::{AtomicBool, Ordering},
     thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
+use crate::error::{RecvTimeoutError, TryRecvError};
+
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    sender_dropped: AtomicBool,
 }
 
 pub struct Sender<'a, T> {
@@ -31,6 +35,7 @@ impl<T> Channel<T> {
         Channel {
             message: UnsafeCell::new(MaybeUninit::uninit()),
             ready: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
         }
     }
 
@@ -71,6 +76,15 @@ impl<T> Sender<'_, T> {
     }
 }
 
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        // Also after `send`, which consumes (and so drops) the sender.
+        self.channel.sender_dropped.store(true, Ordering::Release);
+        // Wake up a receiver in `receive_timeout`, so it doesn't wait until the timeout.
+        self.receiveing_thread.unpark();
+    }
+}
+
 impl<T> Receiver<'_, T> {
     pub fn receive(self) -> T {
         // Block until the channel is ready
@@ -79,6 +93,51 @@ impl<T> Receiver<'_, T> {
         }
         unsafe { (*self.channel.message.get()).assume_init_read() }
     }
+
+    /// Receive the message if it has been sent, without blocking.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        // Load this before `ready`: if the sender is gone, a message it sent
+        // before is visible to the swap below.
+        let disconnected = self.channel.sender_dropped.load(Ordering::Acquire);
+        if self.channel.ready.swap(false, Ordering::Acquire) {
+            return Ok(unsafe { (*self.channel.message.get()).assume_init_read() });
+        }
+        if disconnected {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Block until the message is sent (or the sender is dropped),
+    /// but for no longer than `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            // The sender unparks us, so no need to poll.
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(RecvTimeoutError::Timeout);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+                None => thread::park(),
+            }
+        }
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> Drop for Channel<T> {
@@ -117,4 +176,47 @@ mod tests {
         // OK to split here
         channel.split();
     }
+
+    #[test]
+    fn try_receive() {
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Empty));
+        sender.send(1);
+        assert_eq!(receiver.try_receive(), Ok(1));
+        // Only once.
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let mut channel = Channel::new();
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            assert_eq!(
+                receiver.receive_timeout(Duration::from_millis(10)),
+                Err(RecvTimeoutError::Timeout)
+            );
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                sender.send("Hello, World!");
+            });
+            assert_eq!(
+                receiver.receive_timeout(Duration::from_secs(10)),
+                Ok("Hello, World!")
+            );
+        });
+
+        thread::scope(|s| {
+            let (sender, receiver) = channel.split();
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(sender);
+            });
+            assert_eq!(
+                receiver.receive_timeout(Duration::from_secs(10)),
+                Err(RecvTimeoutError::Disconnected)
+            );
+        });
+    }
 }
@@ -0,0 +1,489 @@
+use std::{
+    cell::UnsafeCell,
+    future::poll_fn,
+    mem::{self, MaybeUninit},
+    sync::{
+        atomic::{fence, AtomicU32, AtomicUsize, Ordering},
+        Mutex,
+    },
+    task::{Poll, Waker},
+    time::{Duration, Instant},
+};
+
+use ch07::cache_padded::CachePadded;
+use ch09::wait_address::{Backend, WaitAddress};
+
+/// A bounded multi-producer multi-consumer channel: a lock-free ring buffer
+/// (Dmitry Vyukov's bounded MPMC queue), where `send` blocks while the buffer
+/// is full and `receive` blocks while it is empty.
+pub struct Channel<T> {
+    buffer: Box<[Slot<T>]>,
+    /// The position of the next message to receive
+    head: CachePadded<AtomicUsize>,
+    /// The position of the next message to send
+    tail: CachePadded<AtomicUsize>,
+    /// Blocked receivers wait on this, senders wake them up.
+    not_empty: Event,
+    /// Blocked senders wait on this, receivers wake them up.
+    not_full: Event,
+}
+
+struct Slot<T> {
+    /// For the slot of position pos (at index pos % capacity):
+    /// 2 * pos: empty, ready for the sender of that position
+    /// 2 * pos + 1: full, ready for the receiver of that position
+    /// (Doubled, so that with a capacity of 1, a full slot of position pos
+    /// can't be mistaken for the empty slot of position pos + 1.)
+    sequence: AtomicUsize,
+    message: UnsafeCell<MaybeUninit<T>>,
+}
+
+unsafe impl<T> Sync for Channel<T> where T: Send {}
+
+impl<T> Channel<T> {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be at least 1");
+        Self {
+            buffer: (0..capacity)
+                .map(|i| Slot {
+                    sequence: AtomicUsize::new(2 * i),
+                    message: UnsafeCell::new(MaybeUninit::uninit()),
+                })
+                .collect(),
+            head: CachePadded::new(AtomicUsize::new(0)),
+            tail: CachePadded::new(AtomicUsize::new(0)),
+            not_empty: Event::new(),
+            not_full: Event::new(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Send a message, or give it back if the channel is full.
+    pub fn try_send(&self, message: T) -> Result<(), T> {
+        self.push(message)?;
+        self.not_empty.notify();
+        Ok(())
+    }
+
+    /// Send a message, blocking while the channel is full.
+    pub fn send(&self, mut message: T) {
+        loop {
+            message = match self.try_send(message) {
+                Ok(()) => return,
+                Err(message) => message,
+            };
+            // Try once more after registering as a waiter, so we can't miss
+            // a receiver freeing a slot in between.
+            let mut unsent = None;
+            let sent = self.not_full.wait_unless(
+                || match self.push(message) {
+                    Ok(()) => Some(()),
+                    Err(m) => {
+                        unsent = Some(m);
+                        None
+                    }
+                },
+                None,
+            );
+            if sent.is_some() {
+                self.not_empty.notify();
+                return;
+            }
+            message = unsent.unwrap();
+        }
+    }
+
+    /// Receive a message, if there is one.
+    pub fn try_receive(&self) -> Option<T> {
+        let message = self.pop()?;
+        self.not_full.notify();
+        Some(message)
+    }
+
+    /// Receive a message, blocking while the channel is empty.
+    pub fn receive(&self) -> T {
+        self.receive_until(None).unwrap()
+    }
+
+    /// Receive a message, blocking while the channel is empty,
+    /// but for no longer than `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Option<T> {
+        self.receive_until(Instant::now().checked_add(timeout))
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> Option<T> {
+        loop {
+            if let Some(message) = self.try_receive() {
+                return Some(message);
+            }
+            let timeout = match deadline {
+                Some(deadline) => Some(deadline.checked_duration_since(Instant::now())?),
+                None => None,
+            };
+            // Try once more after registering as a waiter, so we can't miss
+            // a sender filling a slot in between.
+            if let Some(message) = self.not_empty.wait_unless(|| self.pop(), timeout) {
+                self.not_full.notify();
+                return Some(message);
+            }
+        }
+    }
+
+    /// Receive a message, waiting asynchronously while the channel is empty.
+    pub async fn receive_async(&self) -> T {
+        poll_fn(|cx| {
+            if let Some(message) = self.try_receive() {
+                return Poll::Ready(message);
+            }
+            // Try once more after registering the waker, like `receive_until`.
+            self.not_empty.register(cx.waker());
+            match self.try_receive() {
+                Some(message) => Poll::Ready(message),
+                None => Poll::Pending,
+            }
+        })
+        .await
+    }
+
+    fn push(&self, message: T) -> Result<(), T> {
+        let capacity = self.buffer.len();
+        let mut tail = self.tail.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[tail % capacity];
+            // Acquire: synchronize with the receiver that emptied the slot.
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match sequence.wrapping_sub(tail.wrapping_mul(2)) as isize {
+                // The slot is empty: claim the position.
+                0 => match self.tail.compare_exchange_weak(
+                    tail,
+                    tail.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.message.get()).write(message) };
+                        // Release: hand the message over to the receiver.
+                        slot.sequence
+                            .store(tail.wrapping_mul(2).wrapping_add(1), Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(t) => tail = t,
+                },
+                // The slot still holds the message of the previous lap: full.
+                d if d < 0 => return Err(message),
+                // Another sender claimed this position already.
+                _ => tail = self.tail.load(Ordering::Relaxed),
+            }
+        }
+    }
+
+    fn pop(&self) -> Option<T> {
+        let capacity = self.buffer.len();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.buffer[head % capacity];
+            // Acquire: synchronize with the sender that filled the slot.
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            match sequence.wrapping_sub(head.wrapping_mul(2).wrapping_add(1)) as isize {
+                // The slot is full: claim the position.
+                0 => match self.head.compare_exchange_weak(
+                    head,
+                    head.wrapping_add(1),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let message = unsafe { (*slot.message.get()).assume_init_read() };
+                        // Release: hand the slot over to the sender of the next lap.
+                        slot.sequence.store(
+                            head.wrapping_add(capacity).wrapping_mul(2),
+                            Ordering::Release,
+                        );
+                        return Some(message);
+                    }
+                    Err(h) => head = h,
+                },
+                // The slot hasn't been filled in this lap yet: empty.
+                d if d < 0 => return None,
+                // Another receiver claimed this position already.
+                _ => head = self.head.load(Ordering::Relaxed),
+            }
+        }
+    }
+}
+
+impl<T> Drop for Channel<T> {
+    fn drop(&mut self) {
+        // Drop the messages that were sent but never received.
+        while self.pop().is_some() {}
+    }
+}
+
+/// A futex-based event: a counter that is incremented on every notification,
+/// and the number of threads waiting for it. Plus the wakers of the tasks
+/// waiting for it.
+struct Event {
+    counter: AtomicU32,
+    waiters: AtomicU32,
+    wakers: Mutex<Vec<Waker>>,
+    /// The length of `wakers`, to check without locking it
+    registered_wakers: AtomicUsize,
+}
+
+impl Event {
+    const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            waiters: AtomicU32::new(0),
+            wakers: Mutex::new(Vec::new()),
+            registered_wakers: AtomicUsize::new(0),
+        }
+    }
+
+    /// Wake up one waiting thread, if there are any, and all waiting tasks.
+    fn notify(&self) {
+        // SeqCst, with the SeqCst operations in wait_unless and the fence in
+        // register: either we see the waiter, or the waiter's retry sees the
+        // change we notify about.
+        self.counter.fetch_add(1, Ordering::SeqCst);
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            Backend::wake_one(&self.counter);
+        }
+        if self.registered_wakers.load(Ordering::SeqCst) > 0 {
+            // All of them: a registered task might not be waiting anymore (e.g.
+            // its future was dropped), so waking only one could leave the others
+            // waiting with a message available.
+            let wakers = {
+                let mut wakers = self.wakers.lock().unwrap();
+                self.registered_wakers.store(0, Ordering::SeqCst);
+                mem::take(&mut *wakers)
+            };
+            for waker in wakers {
+                waker.wake();
+            }
+        }
+    }
+
+    /// Register a task's waker, to be woken by the next `notify`.
+    /// The caller must try once more afterwards, like in `wait_unless`.
+    fn register(&self, waker: &Waker) {
+        let mut wakers = self.wakers.lock().unwrap();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.registered_wakers.store(wakers.len(), Ordering::SeqCst);
+        drop(wakers);
+        // Store-load pairing with notify, which changes the channel, then
+        // increments the counter and loads registered_wakers: with this fence
+        // between our registered_wakers store and the caller's retry, either
+        // notify sees our waker, or the retry sees the change it notifies about.
+        fence(Ordering::SeqCst);
+    }
+
+    /// Register as a waiter and try `f` once more. If that fails, sleep until
+    /// notified (or spuriously woken up, or timed out), and return `None`.
+    fn wait_unless<R>(
+        &self,
+        f: impl FnOnce() -> Option<R>,
+        timeout: Option<Duration>,
+    ) -> Option<R> {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        let counter = self.counter.load(Ordering::SeqCst);
+        let result = f();
+        if result.is_none() {
+            Backend::wait(&self.counter, counter, timeout);
+        }
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Channel;
+    use crate::block_on::block_on;
+    use std::{
+        rc::Rc,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn try_send_gives_message_back_when_full() {
+        let c = Channel::new(2);
+        assert_eq!(c.try_send(1), Ok(()));
+        assert_eq!(c.try_send(2), Ok(()));
+        assert_eq!(c.try_send(3), Err(3));
+        assert_eq!(c.try_receive(), Some(1));
+        assert_eq!(c.try_send(3), Ok(()));
+        assert_eq!(c.try_receive(), Some(2));
+        assert_eq!(c.try_receive(), Some(3));
+        assert_eq!(c.try_receive(), None);
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let c = Channel::<i32>::new(1);
+        let start = Instant::now();
+        assert_eq!(c.receive_timeout(Duration::from_millis(20)), None);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_millis(10));
+                c.send(42);
+            });
+            assert_eq!(c.receive_timeout(Duration::from_secs(10)), Some(42));
+        });
+    }
+
+    #[test]
+    fn send_blocks_while_full() {
+        let c = Channel::new(1);
+        let sent = AtomicUsize::new(0);
+        thread::scope(|s| {
+            s.spawn(|| {
+                for i in 0..3 {
+                    c.send(i);
+                    sent.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+            thread::sleep(Duration::from_millis(50));
+            // One message fits, the second send is blocked.
+            assert_eq!(sent.load(Ordering::Relaxed), 1);
+            for i in 0..3 {
+                assert_eq!(c.receive(), i);
+            }
+        });
+        assert_eq!(sent.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn drops_unreceived_messages() {
+        let rc = Rc::new(());
+        let c = Channel::new(4);
+        for _ in 0..3 {
+            c.try_send(rc.clone()).unwrap();
+        }
+        drop(c.try_receive());
+        assert_eq!(Rc::strong_count(&rc), 3);
+        drop(c);
+        assert_eq!(Rc::strong_count(&rc), 1);
+    }
+
+    #[test]
+    fn stress_no_loss_no_duplication() {
+        stress(4);
+    }
+
+    #[test]
+    fn stress_capacity_one() {
+        stress(1);
+    }
+
+    /// Small capacities, so that both senders and receivers block a lot.
+    fn stress(capacity: usize) {
+        const PRODUCERS: usize = 4;
+        const CONSUMERS: usize = 4;
+        const MESSAGES: usize = 20_000;
+
+        let c = Channel::new(capacity);
+        let received: Vec<Vec<(usize, usize)>> = thread::scope(|s| {
+            let consumers: Vec<_> = (0..CONSUMERS)
+                .map(|k| {
+                    let c = &c;
+                    s.spawn(move || {
+                        let mut received = Vec::new();
+                        loop {
+                            // Half of the consumers use (short) timeouts, which
+                            // must not lose messages either.
+                            let m = if k % 2 == 0 {
+                                c.receive()
+                            } else {
+                                match c.receive_timeout(Duration::from_micros(50)) {
+                                    Some(m) => m,
+                                    None => continue,
+                                }
+                            };
+                            match m {
+                                (usize::MAX, _) => break received,
+                                m => received.push(m),
+                            }
+                        }
+                    })
+                })
+                .collect();
+            let producers: Vec<_> = (0..PRODUCERS)
+                .map(|p| {
+                    let c = &c;
+                    s.spawn(move || {
+                        for i in 0..MESSAGES {
+                            c.send((p, i));
+                        }
+                    })
+                })
+                .collect();
+            for t in producers {
+                t.join().unwrap();
+            }
+            // One end marker for each consumer, after all other messages.
+            for _ in 0..CONSUMERS {
+                c.send((usize::MAX, 0));
+            }
+            consumers.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+
+        let mut seen = vec![vec![false; MESSAGES]; PRODUCERS];
+        for messages in &received {
+            // Messages of one producer arrive in order at each consumer.
+            let mut last = [None; PRODUCERS];
+            for &(p, i) in messages {
+                assert!(!seen[p][i], "message {p}/{i} received twice");
+                seen[p][i] = true;
+                assert!(last[p] < Some(i));
+                last[p] = Some(i);
+            }
+        }
+        assert!(seen.iter().flatten().all(|&s| s), "messages were lost");
+    }
+
+    #[test]
+    fn receive_async() {
+        const MESSAGES: usize = 10_000;
+        let c = Channel::new(2);
+        let received = thread::scope(|s| {
+            // Tasks on two threads, each with its own block_on.
+            let receivers: Vec<_> = (0..2)
+                .map(|_| {
+                    s.spawn(|| {
+                        block_on(async {
+                            let mut received = Vec::new();
+                            loop {
+                                match c.receive_async().await {
+                                    usize::MAX => break received,
+                                    m => received.push(m),
+                                }
+                            }
+                        })
+                    })
+                })
+                .collect();
+            for i in 0..MESSAGES {
+                c.send(i);
+            }
+            c.send(usize::MAX);
+            c.send(usize::MAX);
+            let mut received: Vec<usize> = receivers
+                .into_iter()
+                .flat_map(|t| t.join().unwrap())
+                .collect();
+            received.sort();
+            received
+        });
+        assert_eq!(received, (0..MESSAGES).collect::<Vec<_>>());
+    }
+}
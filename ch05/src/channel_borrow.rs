@@ -2,11 +2,20 @@ use std::{
     cell::UnsafeCell,
     mem::MaybeUninit,
     sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
 };
 
+use crate::error::{RecvTimeoutError, TryRecvError};
+
+/// Nobody unparks the receiver when a message arrives (unless the user does it),
+/// so `receive_timeout` checks again at least this often.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
 pub struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
     ready: AtomicBool,
+    sender_dropped: AtomicBool,
 }
 
 pub struct Sender<'a, T> {
@@ -25,6 +34,7 @@ impl<T> Channel<T> {
         Channel {
             message: UnsafeCell::new(MaybeUninit::uninit()),
             ready: AtomicBool::new(false),
+            sender_dropped: AtomicBool::new(false),
         }
     }
 
@@ -55,18 +65,69 @@ impl<T> Sender<'_, T> {
     }
 }
 
+impl<T> Drop for Sender<'_, T> {
+    fn drop(&mut self) {
+        // Also after `send`, which consumes (and so drops) the sender.
+        self.channel.sender_dropped.store(true, Ordering::Release);
+    }
+}
+
 impl<T> Receiver<'_, T> {
     pub fn is_ready(&self) -> bool {
         self.channel.ready.load(Ordering::Relaxed)
     }
 
     pub fn receive(self) -> T {
-        if !self.channel.ready.load(Ordering::Acquire) {
+        // Reset the flag, so the channel doesn't drop the message again.
+        if !self.channel.ready.swap(false, Ordering::Acquire) {
             panic!("no message available");
         }
 
         unsafe { (*self.channel.message.get()).assume_init_read() }
     }
+
+    /// Receive the message if it has been sent, without blocking.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        // Load this before `ready`: if the sender is gone, a message it sent
+        // before is visible to the swap below.
+        let disconnected = self.channel.sender_dropped.load(Ordering::Acquire);
+        // Swap, so the message can only be taken once.
+        if self.channel.ready.swap(false, Ordering::Acquire) {
+            return Ok(unsafe { (*self.channel.message.get()).assume_init_read() });
+        }
+        if disconnected {
+            Err(TryRecvError::Disconnected)
+        } else {
+            Err(TryRecvError::Empty)
+        }
+    }
+
+    /// Wait for the message, but for no longer than `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            let mut wait = POLL_INTERVAL;
+            if let Some(deadline) = deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(RecvTimeoutError::Timeout);
+                }
+                wait = wait.min(deadline - now);
+            }
+            thread::park_timeout(wait);
+        }
+    }
+}
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T> Drop for Channel<T> {
@@ -82,7 +143,6 @@ impl<T> Drop for Channel<T> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::thread;
 
     #[test]
     fn test_can_send_once() {
@@ -109,4 +169,43 @@ mod tests {
         // OK to split here
         channel.split();
     }
+
+    #[test]
+    fn try_receive() {
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Empty));
+        sender.send(1);
+        assert_eq!(receiver.try_receive(), Ok(1));
+        // Only once.
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn try_receive_dropped_sender() {
+        let mut channel = Channel::<i32>::new();
+        let (sender, receiver) = channel.split();
+        drop(sender);
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let mut channel = Channel::new();
+        let (sender, receiver) = channel.split();
+        assert_eq!(
+            receiver.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                sender.send("Hello, World!");
+            });
+            assert_eq!(
+                receiver.receive_timeout(Duration::from_secs(10)),
+                Ok("Hello, World!")
+            );
+        });
+    }
 }
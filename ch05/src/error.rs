@@ -0,0 +1,53 @@
+//! Errors of the receive operations of the oneshot channels.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TryRecvError {
+    /// No message yet, but the sender may still send one.
+    Empty,
+    /// The sender is gone without sending, or the message was already received.
+    Disconnected,
+}
+
+impl fmt::Display for TryRecvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TryRecvError::Empty => "receiving on an empty channel".fmt(f),
+            TryRecvError::Disconnected => "receiving on a disconnected channel".fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for TryRecvError {}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvTimeoutError {
+    /// No message was sent before the timeout expired.
+    Timeout,
+    /// The sender is gone without sending, or the message was already received.
+    Disconnected,
+}
+
+impl fmt::Display for RecvTimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RecvTimeoutError::Timeout => "timed out waiting on a channel".fmt(f),
+            RecvTimeoutError::Disconnected => "receiving on a disconnected channel".fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for RecvTimeoutError {}
+
+/// Returned by a blocking receive when the sender was dropped without sending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Canceled;
+
+impl fmt::Display for Canceled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "the sender was dropped without sending".fmt(f)
+    }
+}
+
+impl std::error::Error for Canceled {}
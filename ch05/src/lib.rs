@@ -4,3 +4,7 @@ pub mod panic_oneshot_channel;
 pub mod safety_through_types;
 pub mod channel_borrow;
 pub mod blocking_channel;
+pub mod bounded_channel;
+pub mod error;
+pub mod atomic_waker;
+pub mod block_on;
@@ -67,6 +67,12 @@ impl<T> Channel<T> {
     }
 }
 
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
         // Here we don't need to use an atomic operation, because
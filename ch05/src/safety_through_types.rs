@@ -1,12 +1,32 @@
 use std::{
-    cell::UnsafeCell,
+    cell::{Cell, UnsafeCell},
+    future::Future,
+    marker::PhantomData,
     mem::MaybeUninit,
+    pin::Pin,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicU8, Ordering},
         Arc,
     },
+    task::{Context, Poll},
+    thread::{self, Thread},
+    time::{Duration, Instant},
 };
 
+use crate::atomic_waker::AtomicWaker;
+use crate::error::{Canceled, RecvTimeoutError, TryRecvError};
+
+/// No message yet
+const EMPTY: u8 = 0;
+/// No message yet, and the receiver stored its thread in `receiving_thread`
+const WAITING: u8 = 1;
+/// The message has been sent
+const READY: u8 = 2;
+/// The sender was dropped without sending
+const CANCELED: u8 = 3;
+/// The message has been received
+const RECEIVED: u8 = 4;
+
 /// Create a pair of (sender, receiver) which can be used
 /// to send and receive one single message
 pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
@@ -17,6 +37,7 @@ pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
         Sender { channel },
         Receiver {
             channel: channel_clone,
+            _not_sync: PhantomData,
         },
     )
 }
@@ -26,9 +47,21 @@ pub struct Sender<T> {
     channel: Arc<Channel<T>>,
 }
 
+/// The receiver can be sent to another thread, but not shared between threads:
+/// only one thread at a time may register itself with the channel.
+///
+/// ```compile_fail
+/// let (_sender, receiver) = ch05::safety_through_types::channel::<i32>();
+/// std::thread::scope(|s| {
+///     s.spawn(|| receiver.receive_timeout(std::time::Duration::from_secs(1)));
+///     receiver.receive_timeout(std::time::Duration::from_secs(1))
+/// });
+/// ```
 pub struct Receiver<T> {
     // Using Arc because the channel is shared between the sender and the receiver
     channel: Arc<Channel<T>>,
+    // Cell is Send, but not Sync
+    _not_sync: PhantomData<Cell<()>>,
 }
 
 impl<T> Sender<T> {
@@ -37,33 +70,147 @@ impl<T> Sender<T> {
         // `send` can be called only once.
         // No need to `panic` any more!
         unsafe { (*self.channel.message.get()).write(message) };
-        self.channel.ready.store(true, Ordering::Release);
+        // Release: hand over the message. Acquire: see the receiving thread.
+        if self.channel.state.swap(READY, Ordering::AcqRel) == WAITING {
+            self.channel.unpark_receiver();
+        }
+        self.channel.waker.wake();
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        // Also runs after `send`, which consumes (and so drops) the sender,
+        // but then the state is READY and stays that way.
+        let result = self
+            .channel
+            .state
+            .fetch_update(Ordering::Acquire, Ordering::Relaxed, |s| {
+                matches!(s, EMPTY | WAITING).then_some(CANCELED)
+            });
+        if result == Ok(WAITING) {
+            self.channel.unpark_receiver();
+        }
+        if result.is_ok() {
+            self.channel.waker.wake();
+        }
     }
 }
 
 impl<T> Receiver<T> {
     pub fn is_ready(&self) -> bool {
-        // Use relaxed ordering, because the load inside `receive` with
+        // Use relaxed ordering, because the compare_exchange inside `try_receive` with
         // acquire ordering will ensure the necessary synchronization with Sender::send.
-        self.channel.ready.load(Ordering::Relaxed)
+        self.channel.state.load(Ordering::Relaxed) == READY
     }
 
-    pub fn receive(self) -> T {
+    /// Block until the message is sent, or until the sender is dropped without sending.
+    ///
+    /// The receiver can be sent to, and used on, any thread: it registers the
+    /// current thread with the channel only when it needs to block.
+    pub fn receive(self) -> Result<T, Canceled> {
         // Now receive takes `self` by value and consumes it, ensuring that `receive` cannot be called
         // at most once.
-        if !self.channel.ready.load(Ordering::Acquire) {
-            panic!("No message available!");
+        self.receive_until(None).map_err(|_| Canceled)
+    }
+
+    /// Receive the message if it has been sent, without blocking or panicking.
+    pub fn try_receive(&self) -> Result<T, TryRecvError> {
+        // Acquire: synchronize with Sender::send. The state changes to RECEIVED,
+        // so the message can only be taken once.
+        match self.channel.state.compare_exchange(
+            READY,
+            RECEIVED,
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            // Safety: The state was READY, so the message has been initialized
+            Ok(_) => Ok(unsafe { (*self.channel.message.get()).assume_init_read() }),
+            Err(EMPTY | WAITING) => Err(TryRecvError::Empty),
+            Err(_) => Err(TryRecvError::Disconnected),
         }
+    }
 
-        // Safety: The check above ensure that message has been initialized
-        unsafe { (*self.channel.message.get()).assume_init_read() }
+    /// Block until the message is sent (or the sender is dropped),
+    /// but for no longer than `timeout`.
+    pub fn receive_timeout(&self, timeout: Duration) -> Result<T, RecvTimeoutError> {
+        self.receive_until(Instant::now().checked_add(timeout))
+    }
+
+    fn receive_until(&self, deadline: Option<Instant>) -> Result<T, RecvTimeoutError> {
+        loop {
+            match self.try_receive() {
+                Ok(message) => return Ok(message),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) => {}
+            }
+            if !self.register() {
+                // The state changed in the meantime, so look again.
+                continue;
+            }
+            // The sender changes the state before unparking us.
+            // (thread::park() can return spuriously, so check the state.)
+            while self.channel.state.load(Ordering::Relaxed) == WAITING {
+                match deadline {
+                    Some(deadline) => {
+                        let now = Instant::now();
+                        if now >= deadline {
+                            return Err(RecvTimeoutError::Timeout);
+                        }
+                        thread::park_timeout(deadline - now);
+                    }
+                    None => thread::park(),
+                }
+            }
+        }
+    }
+
+    /// Store the current thread in the channel, for the sender to unpark.
+    /// Returns false if the sender sent the message or was dropped in the meantime.
+    fn register(&self) -> bool {
+        let state = &self.channel.state;
+        // An earlier call might have registered already, possibly on another thread,
+        // so take the slot back first. After that, the sender won't look at the slot.
+        if state
+            .compare_exchange(WAITING, EMPTY, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+            && state.load(Ordering::Relaxed) != EMPTY
+        {
+            return false;
+        }
+        unsafe { *self.channel.receiving_thread.get() = Some(thread::current()) };
+        // Release: make the thread visible to the sender.
+        state
+            .compare_exchange(EMPTY, WAITING, Ordering::Release, Ordering::Relaxed)
+            .is_ok()
+    }
+}
+
+/// The receiver can also be awaited, instead of blocking the thread.
+impl<T> Future for Receiver<T> {
+    type Output = Result<T, Canceled>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // Register before checking the state: if the sender changes it right after
+        // the check, it wakes the waker we registered.
+        self.channel.waker.register(cx.waker());
+        match self.try_receive() {
+            Ok(message) => Poll::Ready(Ok(message)),
+            Err(TryRecvError::Empty) => Poll::Pending,
+            Err(TryRecvError::Disconnected) => Poll::Ready(Err(Canceled)),
+        }
     }
 }
 
 // Private now
 struct Channel<T> {
     message: UnsafeCell<MaybeUninit<T>>,
-    ready: AtomicBool,
+    // Only accessed by the receiver while the state is EMPTY, and by the
+    // sender after it changed the state from WAITING.
+    receiving_thread: UnsafeCell<Option<Thread>>,
+    // For a receiver that is awaited rather than blocking
+    waker: AtomicWaker,
+    state: AtomicU8,
 }
 
 unsafe impl<T> Sync for Channel<T> where T: Send {}
@@ -72,14 +219,23 @@ impl<T> Channel<T> {
     const fn new() -> Self {
         Channel {
             message: UnsafeCell::new(MaybeUninit::uninit()),
-            ready: AtomicBool::new(false),
+            receiving_thread: UnsafeCell::new(None),
+            waker: AtomicWaker::new(),
+            state: AtomicU8::new(EMPTY),
+        }
+    }
+
+    /// Only call this after changing the state from WAITING!
+    fn unpark_receiver(&self) {
+        if let Some(t) = unsafe { (*self.receiving_thread.get()).take() } {
+            t.unpark();
         }
     }
 }
 
 impl<T> Drop for Channel<T> {
     fn drop(&mut self) {
-        if *self.ready.get_mut() {
+        if *self.state.get_mut() == READY {
             unsafe {
                 self.message.get_mut().assume_init_drop();
             }
@@ -89,8 +245,10 @@ impl<T> Drop for Channel<T> {
 
 #[cfg(test)]
 mod tests {
+    use crate::block_on::block_on;
+    use crate::error::{Canceled, RecvTimeoutError, TryRecvError};
     use crate::safety_through_types::channel;
-    use std::thread;
+    use std::{thread, time::Duration};
 
     #[test]
     fn can_send_and_receive_message_once() {
@@ -113,7 +271,108 @@ mod tests {
                 thread::park();
             }
 
-            assert_eq!(receiver.receive(), "Hello, World!");
+            assert_eq!(receiver.receive(), Ok("Hello, World!"));
         });
     }
+
+    #[test]
+    fn try_receive() {
+        let (sender, receiver) = channel();
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Empty));
+        sender.send(1);
+        assert_eq!(receiver.try_receive(), Ok(1));
+        // Only once.
+        assert_eq!(receiver.try_receive(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn receive_timeout() {
+        let (sender, receiver) = channel();
+        assert_eq!(
+            receiver.receive_timeout(Duration::from_millis(10)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(sender);
+        });
+        // Doesn't wait for the full timeout.
+        assert_eq!(
+            receiver.receive_timeout(Duration::from_secs(10)),
+            Err::<i32, _>(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn receive_blocks_until_sent() {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send("Hello, World!");
+        });
+        // No need to check is_ready first anymore.
+        assert_eq!(receiver.receive(), Ok("Hello, World!"));
+    }
+
+    #[test]
+    fn canceled() {
+        let (sender, receiver) = channel::<i32>();
+        drop(sender);
+        assert_eq!(receiver.receive(), Err(Canceled));
+
+        // Also while blocked.
+        let (sender, receiver) = channel::<i32>();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(sender);
+        });
+        assert_eq!(receiver.receive(), Err(Canceled));
+    }
+
+    #[test]
+    fn receive_on_another_thread() {
+        // The receiver is not tied to the thread that created the channel,
+        // even after it registered a thread in an earlier call.
+        let (sender, receiver) = channel();
+        assert_eq!(
+            receiver.receive_timeout(Duration::from_millis(1)),
+            Err(RecvTimeoutError::Timeout)
+        );
+        let t = thread::spawn(move || receiver.receive());
+        thread::sleep(Duration::from_millis(10));
+        sender.send(42);
+        assert_eq!(t.join().unwrap(), Ok(42));
+    }
+
+    #[test]
+    fn await_receiver() {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send("Hello, World!");
+        });
+        assert_eq!(block_on(receiver), Ok("Hello, World!"));
+
+        let (sender, receiver) = channel::<i32>();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            drop(sender);
+        });
+        let result = block_on(async {
+            let result = receiver.await;
+            result.map(|n| n + 1)
+        });
+        assert_eq!(result, Err(Canceled));
+    }
+
+    #[test]
+    fn await_many() {
+        // Race sending with polling.
+        for i in 0..1000 {
+            let (sender, receiver) = channel();
+            let t = thread::spawn(move || sender.send(i));
+            assert_eq!(block_on(receiver), Ok(i));
+            t.join().unwrap();
+        }
+    }
 }
@@ -1,15 +1,27 @@
 use std::collections::VecDeque;
-use std::sync::{Condvar, Mutex};
+use std::fmt;
+use std::sync::{Arc, Condvar, Mutex};
 
 pub struct Channel<T> {
-    queue: Mutex<VecDeque<T>>,
+    state: Mutex<State<T>>,
     item_ready: Condvar,
 }
 
+struct State<T> {
+    queue: VecDeque<T>,
+    // Only used through `Sender` and `Receiver`
+    senders: usize,
+    receiver_dropped: bool,
+}
+
 impl<T> Channel<T> {
     pub fn new() -> Self {
         Self {
-            queue: Mutex::new(VecDeque::new()),
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                senders: 0,
+                receiver_dropped: false,
+            }),
             item_ready: Condvar::new(),
         }
     }
@@ -17,16 +29,16 @@ impl<T> Channel<T> {
     pub fn send(&self, message: T) {
         // Push the message to the back of the queue
         // and notify one waiting receiver (if there are some)
-        self.queue.lock().unwrap().push_back(message);
+        self.state.lock().unwrap().queue.push_back(message);
         self.item_ready.notify_one();
     }
 
     pub fn receive(&self) -> T {
         // Lock the mutex
-        let mut b = self.queue.lock().unwrap();
+        let mut b = self.state.lock().unwrap();
         loop {
             // Pop one message from the queue, if there are some
-            if let Some(message) = b.pop_front() {
+            if let Some(message) = b.queue.pop_front() {
                 return message;
             }
 
@@ -43,3 +55,236 @@ impl<T> Default for Channel<T> {
         Self::new()
     }
 }
+
+/// Create a pair of (sender, receiver) handles to a new channel.
+/// The sender can be cloned to send from multiple threads.
+///
+/// Unlike `Channel::send` and `Channel::receive`, these notice when the other side
+/// is gone: receiving fails once all senders are dropped (and all messages are
+/// received), and sending fails once the receiver is dropped.
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let channel = Arc::new(Channel::new());
+    channel.state.lock().unwrap().senders = 1;
+    (
+        Sender {
+            channel: channel.clone(),
+        },
+        Receiver { channel },
+    )
+}
+
+pub struct Sender<T> {
+    channel: Arc<Channel<T>>,
+}
+
+pub struct Receiver<T> {
+    channel: Arc<Channel<T>>,
+}
+
+/// Returned by `Receiver::receive` when the channel is empty and all senders are gone.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "receiving on an empty channel without senders".fmt(f)
+    }
+}
+
+impl std::error::Error for Disconnected {}
+
+/// Returned by `Sender::send` when the receiver is gone, with the message that
+/// could not be sent.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SendError<T>(pub T);
+
+impl<T> fmt::Debug for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Like std's SendError: don't require T: Debug.
+        f.debug_struct("SendError").finish_non_exhaustive()
+    }
+}
+
+impl<T> fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        "sending on a channel without receiver".fmt(f)
+    }
+}
+
+impl<T> std::error::Error for SendError<T> {}
+
+impl<T> Sender<T> {
+    pub fn send(&self, message: T) -> Result<(), SendError<T>> {
+        let mut state = self.channel.state.lock().unwrap();
+        // Nobody will ever receive it, so give it back.
+        if state.receiver_dropped {
+            return Err(SendError(message));
+        }
+        state.queue.push_back(message);
+        drop(state);
+        self.channel.item_ready.notify_one();
+        Ok(())
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.channel.state.lock().unwrap().senders += 1;
+        Self {
+            channel: self.channel.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            // Wake up the receiver, which would otherwise wait forever.
+            self.channel.item_ready.notify_all();
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Block until a message is available, or until all senders are gone.
+    /// Messages sent before the last sender was dropped are still received.
+    pub fn receive(&self) -> Result<T, Disconnected> {
+        let mut state = self.channel.state.lock().unwrap();
+        loop {
+            if let Some(message) = state.queue.pop_front() {
+                return Ok(message);
+            }
+            // Checked while holding the lock, so we can't miss the last sender's
+            // notification: it decrements the count while holding the lock too.
+            if state.senders == 0 {
+                return Err(Disconnected);
+            }
+            state = self.channel.item_ready.wait(state).unwrap();
+        }
+    }
+
+    /// An iterator over the received messages, which ends once all senders are gone.
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter { receiver: self }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        let mut state = self.channel.state.lock().unwrap();
+        state.receiver_dropped = true;
+        // Nobody will receive these anymore, so drop them now rather than
+        // when the last sender is gone.
+        let messages = std::mem::take(&mut state.queue);
+        drop(state);
+        drop(messages);
+    }
+}
+
+pub struct Iter<'a, T> {
+    receiver: &'a Receiver<T>,
+}
+
+impl<T> Iterator for Iter<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+pub struct IntoIter<T> {
+    receiver: Receiver<T>,
+}
+
+impl<T> Iterator for IntoIter<T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        self.receiver.receive().ok()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Receiver<T> {
+    type Item = T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T> IntoIterator for Receiver<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> IntoIter<T> {
+        IntoIter { receiver: self }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{rc::Rc, thread, time::Duration};
+
+    #[test]
+    fn receive_after_senders_dropped() {
+        let (sender, receiver) = channel();
+        let sender2 = sender.clone();
+        sender.send(1).unwrap();
+        sender2.send(2).unwrap();
+        drop(sender);
+        drop(sender2);
+        // Messages sent before are still received.
+        assert_eq!(receiver.receive(), Ok(1));
+        assert_eq!(receiver.receive(), Ok(2));
+        assert_eq!(receiver.receive(), Err(Disconnected));
+    }
+
+    #[test]
+    fn blocked_receiver_wakes_up_on_disconnect() {
+        let (sender, receiver) = channel::<i32>();
+        thread::scope(|s| {
+            s.spawn(move || {
+                thread::sleep(Duration::from_millis(10));
+                drop(sender);
+            });
+            assert_eq!(receiver.receive(), Err(Disconnected));
+        });
+    }
+
+    #[test]
+    fn send_after_receiver_dropped() {
+        let (sender, receiver) = channel();
+        let rc = Rc::new(());
+        sender.send(rc.clone()).unwrap();
+        drop(receiver);
+        // The unreceived message was dropped with the receiver.
+        assert_eq!(Rc::strong_count(&rc), 1);
+        let SendError(message) = sender.send(rc.clone()).unwrap_err();
+        assert!(Rc::ptr_eq(&message, &rc));
+    }
+
+    #[test]
+    fn iterator_ends_on_disconnect() {
+        let (sender, receiver) = channel();
+        thread::scope(|s| {
+            for i in 0..4 {
+                let sender = sender.clone();
+                s.spawn(move || {
+                    for j in 0..100 {
+                        sender.send(i * 100 + j).unwrap();
+                    }
+                });
+            }
+            drop(sender);
+            let mut received: Vec<i32> = receiver.into_iter().collect();
+            received.sort();
+            assert_eq!(received, (0..400).collect::<Vec<_>>());
+        });
+    }
+}
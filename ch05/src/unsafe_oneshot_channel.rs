@@ -45,3 +45,9 @@ impl <T> Channel<T> {
         (*self.message.get()).assume_init_read()
     }
 }
+
+impl<T> Default for Channel<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
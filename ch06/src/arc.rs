@@ -125,7 +125,7 @@ fn test() {
     assert_eq!(y.0, "hello");
 
     // deref_mut returns None if the Arc is shared
-    if let Some(_) = Arc::get_mut(&mut y) {
+    if Arc::get_mut(&mut y).is_some() {
         panic!("--- shared Arc should not provide mutable access to internal data ---");
     }
 
@@ -136,7 +136,7 @@ fn test() {
     assert_eq!(NUM_DROPS.load(Ordering::Relaxed), 0);
 
     // Now y is the only Arc. get_mut should return Some.
-    if let None = Arc::get_mut(&mut y) {
+    if Arc::get_mut(&mut y).is_none() {
         panic!(
             "--- Arc with refcount = 1 should provide mutable access to the underlying data ---"
         );
@@ -0,0 +1,147 @@
+use std::{
+    env, fmt, process,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Instant,
+};
+
+/// How to run a benchmark. Can be overridden on the command line with
+/// `--warmup N`, `--samples N` and `--iterations N`.
+#[derive(Clone, Copy, Debug)]
+pub struct Config {
+    /// Number of runs to throw away first, to warm up caches and CPU frequency
+    pub warmup: usize,
+    /// Number of runs to measure
+    pub samples: usize,
+    /// Number of iterations per run
+    pub iterations: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            warmup: 2,
+            samples: 10,
+            iterations: 100_000_000,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args() -> Self {
+        let mut config = Self::default();
+        let mut args = env::args().skip(1);
+        while let Some(flag) = args.next() {
+            let value = args.next().and_then(|v| v.parse::<u64>().ok());
+            match (flag.as_str(), value) {
+                ("--warmup", Some(v)) => config.warmup = v as usize,
+                ("--samples", Some(v)) if v >= 2 => config.samples = v as usize,
+                ("--iterations", Some(v)) if v >= 1 => config.iterations = v,
+                _ => {
+                    eprintln!(
+                        "usage: [--warmup N] [--samples N (at least 2)] [--iterations N (at least 1)]"
+                    );
+                    process::exit(2);
+                }
+            }
+        }
+        config
+    }
+}
+
+/// The time per iteration over all samples, in nanoseconds.
+#[derive(Clone, Copy, Debug)]
+pub struct Summary {
+    pub mean: f64,
+    pub stddev: f64,
+    /// Half width of the 95% confidence interval of the mean
+    pub ci95: f64,
+    pub min: f64,
+    pub max: f64,
+    pub samples: usize,
+}
+
+impl Summary {
+    pub fn from_samples(samples: &[f64]) -> Self {
+        assert!(samples.len() >= 2, "need at least two samples");
+        let n = samples.len() as f64;
+        let mean = samples.iter().sum::<f64>() / n;
+        let variance = samples.iter().map(|s| (s - mean).powi(2)).sum::<f64>() / (n - 1.0);
+        let stddev = variance.sqrt();
+        Self {
+            mean,
+            stddev,
+            ci95: t_95(samples.len() - 1) * stddev / n.sqrt(),
+            min: samples.iter().copied().fold(f64::INFINITY, f64::min),
+            max: samples.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+            samples: samples.len(),
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:.3} ns/iter ± {:.3} (95% CI), stddev {:.3}, min {:.3}, max {:.3}, {} samples",
+            self.mean, self.ci95, self.stddev, self.min, self.max, self.samples
+        )
+    }
+}
+
+/// Two-sided 95% quantile of Student's t-distribution with `df` degrees of freedom.
+fn t_95(df: usize) -> f64 {
+    const TABLE: [f64; 30] = [
+        12.706, 4.303, 3.182, 2.776, 2.571, 2.447, 2.365, 2.306, 2.262, 2.228, 2.201, 2.179, 2.160,
+        2.145, 2.131, 2.120, 2.110, 2.101, 2.093, 2.086, 2.080, 2.074, 2.069, 2.064, 2.060, 2.056,
+        2.052, 2.048, 2.045, 2.042,
+    ];
+    TABLE.get(df.wrapping_sub(1)).copied().unwrap_or(1.960)
+}
+
+/// Run `f(config.iterations)` repeatedly, and summarize the time per iteration.
+pub fn bench(config: &Config, mut f: impl FnMut(u64)) -> Summary {
+    for _ in 0..config.warmup {
+        f(config.iterations);
+    }
+
+    let samples: Vec<f64> = (0..config.samples)
+        .map(|_| {
+            let start = Instant::now();
+            f(config.iterations);
+            start.elapsed().as_nanos() as f64 / config.iterations as f64
+        })
+        .collect();
+
+    Summary::from_samples(&samples)
+}
+
+/// Run `f` while another thread keeps calling `background` in a loop.
+pub fn with_background<R>(background: impl Fn() + Sync, f: impl FnOnce() -> R) -> R {
+    let stop = AtomicBool::new(false);
+    thread::scope(|s| {
+        s.spawn(|| {
+            while !stop.load(Ordering::Relaxed) {
+                background();
+            }
+        });
+        let result = f();
+        stop.store(true, Ordering::Relaxed);
+        result
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Summary;
+
+    #[test]
+    fn summary() {
+        let s = Summary::from_samples(&[1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(s.mean, 3.0);
+        assert!((s.stddev - 2.5f64.sqrt()).abs() < 1e-9);
+        // t(4) = 2.776
+        assert!((s.ci95 - 2.776 * 2.5f64.sqrt() / 5f64.sqrt()).abs() < 1e-9);
+        assert_eq!((s.min, s.max), (1.0, 5.0));
+    }
+}
@@ -1,21 +1,26 @@
 use std::{
     hint::black_box,
     sync::atomic::{AtomicU64, Ordering},
-    thread,
-    time::Instant,
 };
 
+use ch07::bench::{bench, with_background, Config};
+
 static A: AtomicU64 = AtomicU64::new(0);
 
 fn main() {
+    let config = Config::from_args();
     black_box(&A);
-    thread::spawn(|| loop {
-        black_box(A.load(Ordering::Relaxed));
-    });
-
-    let start = Instant::now();
-    for _ in 0..1_000_000_000 {
-        black_box(A.load(Ordering::Relaxed));
-    }
-    println!("{:?}", start.elapsed());
+    let summary = with_background(
+        || {
+            black_box(A.load(Ordering::Relaxed));
+        },
+        || {
+            bench(&config, |iterations| {
+                for _ in 0..iterations {
+                    black_box(A.load(Ordering::Relaxed));
+                }
+            })
+        },
+    );
+    println!("{summary}");
 }
@@ -1,29 +1,34 @@
 use std::{
     hint::black_box,
     sync::atomic::{AtomicU64, Ordering},
-    thread,
-    time::Instant,
 };
 
-#[repr(align(64))]
-struct Aligned(AtomicU64);
+use ch07::{
+    bench::{bench, with_background, Config},
+    cache_padded::CachePadded,
+};
 
-static A: [Aligned; 3] = [
-    Aligned(AtomicU64::new(0)),
-    Aligned(AtomicU64::new(0)),
-    Aligned(AtomicU64::new(0)),
+static A: [CachePadded<AtomicU64>; 3] = [
+    CachePadded::new(AtomicU64::new(0)),
+    CachePadded::new(AtomicU64::new(0)),
+    CachePadded::new(AtomicU64::new(0)),
 ];
 
 fn main() {
+    let config = Config::from_args();
     black_box(&A);
-    thread::spawn(|| loop {
-        A[0].0.store(0, Ordering::Relaxed);
-        A[2].0.store(0, Ordering::Relaxed);
-    });
-
-    let start = Instant::now();
-    for _ in 0..1_000_000_000 {
-        black_box(A[1].0.load(Ordering::Relaxed));
-    }
-    println!("{:?}", start.elapsed());
+    let summary = with_background(
+        || {
+            A[0].store(0, Ordering::Relaxed);
+            A[2].store(0, Ordering::Relaxed);
+        },
+        || {
+            bench(&config, |iterations| {
+                for _ in 0..iterations {
+                    black_box(A[1].load(Ordering::Relaxed));
+                }
+            })
+        },
+    );
+    println!("{summary}");
 }
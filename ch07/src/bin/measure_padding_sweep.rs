@@ -0,0 +1,92 @@
+// Sweep over the amount of padding between two atomics, one of which is
+// continuously written by a background thread while we load the other.
+// As soon as the two end up in different cache lines, the loads get fast again:
+// the distance at which that happens is the destructive interference size.
+//
+// Usage: measure_padding_sweep [--warmup N] [--samples N] [--iterations N]
+
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use ch07::bench::{bench, with_background, Config, Summary};
+
+// Aligned to 128 bytes, so `written` always starts a (pair of) cache line(s),
+// and `read` ends up in the same line exactly when their distance is smaller than the line size.
+#[repr(C, align(128))]
+struct Padded<const PAD: usize> {
+    written: AtomicU64,
+    _pad: [u8; PAD],
+    read: AtomicU64,
+}
+
+struct Measurement {
+    pad: usize,
+    distance: usize,
+    summary: Summary,
+}
+
+fn measure<const PAD: usize>(config: &Config) -> Measurement {
+    let a = Padded::<PAD> {
+        written: AtomicU64::new(0),
+        _pad: [0; PAD],
+        read: AtomicU64::new(0),
+    };
+    black_box(&a);
+    let distance = std::mem::offset_of!(Padded<PAD>, read);
+    let summary = with_background(
+        || a.written.store(0, Ordering::Relaxed),
+        || {
+            bench(config, |iterations| {
+                for _ in 0..iterations {
+                    black_box(a.read.load(Ordering::Relaxed));
+                }
+            })
+        },
+    );
+    Measurement {
+        pad: PAD,
+        distance,
+        summary,
+    }
+}
+
+fn main() {
+    let config = Config::from_args();
+
+    let results = [
+        measure::<0>(&config),
+        measure::<8>(&config),
+        measure::<16>(&config),
+        measure::<32>(&config),
+        measure::<64>(&config),
+        measure::<128>(&config),
+    ];
+
+    println!("{:>7} {:>8}  time", "padding", "distance");
+    for m in &results {
+        println!("{:>7} {:>8}  {}", m.pad, m.distance, m.summary);
+    }
+
+    // The furthest apart pair is our baseline without false sharing. Find the smallest
+    // distance from which on every measurement is within 10% of it (or of its CI).
+    let baseline = results[results.len() - 1].summary;
+    let fast = |s: &Summary| s.mean - s.ci95 <= (baseline.mean + baseline.ci95) * 1.1;
+    let first_fast = results
+        .iter()
+        .rposition(|m| !fast(&m.summary))
+        .map_or(0, |slow| slow + 1);
+
+    match first_fast {
+        0 => println!("\nno false sharing measured, even for adjacent atomics"),
+        i => {
+            let (slow, fast) = (results[i - 1].distance, results[i].distance);
+            println!(
+                "\nfalse sharing up to {slow} bytes apart, but not at {fast}: \
+                 the interference size is {} bytes",
+                (slow + 1).next_power_of_two()
+            );
+        }
+    }
+}
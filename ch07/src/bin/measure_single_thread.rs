@@ -1,16 +1,19 @@
 use std::{
     hint::black_box,
     sync::atomic::{AtomicU64, Ordering},
-    time::Instant,
 };
 
+use ch07::bench::{bench, Config};
+
 static A: AtomicU64 = AtomicU64::new(0);
 
 fn main() {
+    let config = Config::from_args();
     black_box(&A);
-    let start = Instant::now();
-    for _ in 0..1_000_000_000 {
-        black_box(A.load(Ordering::Relaxed));
-    }
-    println!("{:?}", start.elapsed());
+    let summary = bench(&config, |iterations| {
+        for _ in 0..iterations {
+            black_box(A.load(Ordering::Relaxed));
+        }
+    });
+    println!("{summary}");
 }
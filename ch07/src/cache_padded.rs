@@ -0,0 +1,80 @@
+use std::ops::{Deref, DerefMut};
+
+/// Aligns and pads a value to the size of a cache line, so it never shares
+/// a cache line with anything else, avoiding false sharing.
+///
+/// On x86_64 this is 128 bytes rather than 64: the CPU prefetches cache lines
+/// in pairs, so two values 64 bytes apart still interfere with each other.
+///
+/// It only pays off for atomics that different threads write independently,
+/// like the two counters of ch09's `FairMutex`. The ch04 spin locks don't use
+/// it: their single state word only shares a cache line with the value it
+/// protects, which the thread holding the lock touches anyway.
+#[cfg_attr(target_arch = "x86_64", repr(align(128)))]
+#[cfg_attr(not(target_arch = "x86_64"), repr(align(64)))]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct CachePadded<T> {
+    value: T,
+}
+
+impl<T> CachePadded<T> {
+    pub const fn new(value: T) -> Self {
+        Self { value }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T> Deref for CachePadded<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for CachePadded<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+impl<T> From<T> for CachePadded<T> {
+    fn from(value: T) -> Self {
+        Self::new(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CachePadded;
+    use std::{
+        mem::{align_of, size_of},
+        sync::atomic::{AtomicU32, Ordering},
+    };
+
+    const LINE: usize = if cfg!(target_arch = "x86_64") { 128 } else { 64 };
+
+    #[test]
+    fn layout() {
+        assert_eq!(align_of::<CachePadded<u8>>(), LINE);
+        assert_eq!(size_of::<CachePadded<u8>>(), LINE);
+        assert_eq!(size_of::<CachePadded<[u8; 130]>>(), LINE * (130 / LINE + 1));
+
+        let a = [CachePadded::new(0u8), CachePadded::new(1u8)];
+        assert_eq!(&a[1] as *const _ as usize - &a[0] as *const _ as usize, LINE);
+    }
+
+    #[test]
+    fn usable_as_state_word() {
+        static STATE: CachePadded<AtomicU32> = CachePadded::new(AtomicU32::new(0));
+        STATE.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(STATE.load(Ordering::Relaxed), 1);
+
+        let mut v = CachePadded::new(vec![1]);
+        v.push(2);
+        assert_eq!(v.into_inner(), [1, 2]);
+    }
+}
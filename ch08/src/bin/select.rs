@@ -0,0 +1,112 @@
+#[cfg(not(target_os = "linux"))]
+fn main() {
+    println!("Unimplemented for non-Linux OSes");
+}
+
+#[cfg(target_os = "linux")]
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+    time::Duration,
+};
+
+#[cfg(target_os = "linux")]
+use ch08::futex::{futex_waitv_private, wake_all_private, WaitvError, WaitvOutcome};
+
+#[cfg(target_os = "linux")]
+const EMPTY: u32 = 0;
+#[cfg(target_os = "linux")]
+const READY: u32 = 1;
+#[cfg(target_os = "linux")]
+const TAKEN: u32 = 2;
+
+/// A minimal one-shot channel, with the state in an AtomicU32 so we can wait on it.
+#[cfg(target_os = "linux")]
+struct Oneshot<T> {
+    state: AtomicU32,
+    message: UnsafeCell<MaybeUninit<T>>,
+}
+
+#[cfg(target_os = "linux")]
+unsafe impl<T> Sync for Oneshot<T> where T: Send {}
+
+#[cfg(target_os = "linux")]
+impl<T> Oneshot<T> {
+    const fn new() -> Self {
+        Self {
+            state: AtomicU32::new(EMPTY),
+            message: UnsafeCell::new(MaybeUninit::uninit()),
+        }
+    }
+
+    /// Safety: only call this once.
+    unsafe fn send(&self, message: T) {
+        (*self.message.get()).write(message);
+        self.state.store(READY, Ordering::Release);
+        wake_all_private(&self.state).unwrap();
+    }
+
+    fn try_receive(&self) -> Option<T> {
+        self.state
+            .compare_exchange(READY, TAKEN, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| unsafe { (*self.message.get()).assume_init_read() })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn main() {
+    let numbers = Oneshot::new();
+    let words = Oneshot::new();
+
+    thread::scope(|s| {
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(300));
+            unsafe { numbers.send(42) };
+        });
+        s.spawn(|| {
+            thread::sleep(Duration::from_millis(100));
+            unsafe { words.send("hello") };
+        });
+
+        // Receive both messages, in whichever order they arrive.
+        let (mut number, mut word) = (None, None);
+        while number.is_none() || word.is_none() {
+            if number.is_none() {
+                number = numbers.try_receive();
+                if let Some(n) = number {
+                    println!("Received {n} from the first channel");
+                }
+            }
+            if word.is_none() {
+                word = words.try_receive();
+                if let Some(w) = word {
+                    println!("Received {w:?} from the second channel");
+                }
+            }
+
+            // Sleep until either of the channels that are still empty changes.
+            let mut waiting = Vec::new();
+            if number.is_none() {
+                waiting.push((&numbers.state, EMPTY));
+            }
+            if word.is_none() {
+                waiting.push((&words.state, EMPTY));
+            }
+            if waiting.is_empty() {
+                break;
+            }
+            match futex_waitv_private(&waiting, None) {
+                Ok(WaitvOutcome::Woken(i)) => println!("Woken up through futex {i}"),
+                Ok(_) => {}
+                Err(WaitvError::Unsupported) => {
+                    // Before Linux 5.16: fall back to polling.
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Err(e) => panic!("futex_waitv failed: {e}"),
+            }
+        }
+    });
+}
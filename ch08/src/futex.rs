@@ -0,0 +1,901 @@
+//! Safe wrappers around the Linux futex syscall.
+//!
+//! Unlike a bare `libc::syscall`, these report what happened: why a wait
+//! returned, how many threads a wake woke up, and any errors.
+//!
+//! The plain functions use shared futexes, which also work between processes
+//! (e.g. on shared memory). The `_private` variants set `FUTEX_PRIVATE_FLAG`,
+//! which lets the kernel skip some work, but only works between threads of
+//! one process. A private wake never wakes a shared waiter and vice versa.
+//!
+//! Besides plain wait and wake, there are the requeue (`cmp_requeue`),
+//! wake-op (`wake_op`) and bitset (`futex_wait_bitset`, `wake_bitset`) operations,
+//! waits with an absolute deadline (`futex_wait_until`), and waiting on
+//! several atomics at once (`futex_waitv`), and the priority-inheritance
+//! operations (`lock_pi`, `trylock_pi`, `unlock_pi`).
+
+use std::{
+    fmt, io, ptr,
+    sync::atomic::AtomicU32,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// An error number returned by the kernel.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Errno(pub i32);
+
+impl Errno {
+    fn last() -> Self {
+        Self(io::Error::last_os_error().raw_os_error().unwrap_or(0))
+    }
+}
+
+impl fmt::Display for Errno {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        io::Error::from_raw_os_error(self.0).fmt(f)
+    }
+}
+
+impl std::error::Error for Errno {}
+
+impl From<Errno> for io::Error {
+    fn from(e: Errno) -> Self {
+        io::Error::from_raw_os_error(e.0)
+    }
+}
+
+/// Why `futex_wait` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitOutcome {
+    /// Woken up by a wake operation, or spuriously.
+    Woken,
+    /// The timeout expired.
+    TimedOut,
+    /// The atomic didn't have the expected value, so we didn't go to sleep at all.
+    ValueMismatch,
+    /// Interrupted by a signal handler.
+    Interrupted,
+}
+
+/// The raw syscall. Returns the (non-negative) result, or the errno.
+///
+/// Safety: `uaddr` must be valid, and `timeout` and `uaddr2` must be
+/// valid or null if `op` uses them.
+pub(crate) unsafe fn futex(
+    uaddr: *const AtomicU32,
+    op: i32,
+    val: u32,
+    timeout: *const libc::timespec,
+    uaddr2: *const AtomicU32,
+    val3: u32,
+) -> Result<i64, Errno> {
+    let r = libc::syscall(libc::SYS_futex, uaddr, op, val, timeout, uaddr2, val3);
+    if r < 0 {
+        Err(Errno::last())
+    } else {
+        Ok(r as i64)
+    }
+}
+
+/// Converts a duration to a timespec, clamping durations that don't fit.
+pub(crate) fn to_timespec(d: Duration) -> libc::timespec {
+    libc::timespec {
+        tv_sec: d.as_secs().min(libc::time_t::MAX as u64) as libc::time_t,
+        tv_nsec: d.subsec_nanos() as libc::c_long,
+    }
+}
+
+/// Turns the result of a wait operation into a `WaitOutcome`.
+pub(crate) fn wait_outcome(r: Result<i64, Errno>) -> Result<WaitOutcome, Errno> {
+    match r {
+        Ok(_) => Ok(WaitOutcome::Woken),
+        Err(Errno(libc::ETIMEDOUT)) => Ok(WaitOutcome::TimedOut),
+        Err(Errno(libc::EAGAIN)) => Ok(WaitOutcome::ValueMismatch),
+        Err(Errno(libc::EINTR)) => Ok(WaitOutcome::Interrupted),
+        Err(e) => Err(e),
+    }
+}
+
+fn wait_raw(
+    a: &AtomicU32,
+    op: i32,
+    expected: u32,
+    timeout: Option<Duration>,
+) -> Result<WaitOutcome, Errno> {
+    // FUTEX_WAIT takes a relative timeout.
+    let timeout = timeout.map(to_timespec);
+    let timeout_ptr = timeout.as_ref().map_or(ptr::null(), |t| t as *const _);
+    wait_outcome(unsafe { futex(a, op, expected, timeout_ptr, ptr::null(), 0) })
+}
+
+fn wake_raw(a: &AtomicU32, op: i32, n: u32) -> Result<usize, Errno> {
+    // The kernel takes the number of threads as a signed int.
+    let n = n.min(i32::MAX as u32);
+    unsafe { futex(a, op, n, ptr::null(), ptr::null(), 0) }.map(|woken| woken as usize)
+}
+
+/// Sleep as long as `a` contains `expected`, until woken up or `timeout` expires.
+///
+/// Like all futex waits, it can also return spuriously, so the caller should
+/// check the atomic again in a loop.
+pub fn futex_wait(
+    a: &AtomicU32,
+    expected: u32,
+    timeout: Option<Duration>,
+) -> Result<WaitOutcome, Errno> {
+    wait_raw(a, libc::FUTEX_WAIT, expected, timeout)
+}
+
+/// Wake up to `n` threads waiting on `a`. Returns the number of threads woken up.
+pub fn wake(a: &AtomicU32, n: u32) -> Result<usize, Errno> {
+    wake_raw(a, libc::FUTEX_WAKE, n)
+}
+
+/// Wake all threads waiting on `a`. Returns the number of threads woken up.
+pub fn wake_all(a: &AtomicU32) -> Result<usize, Errno> {
+    wake(a, u32::MAX)
+}
+
+/// Like `futex_wait`, for a private futex.
+pub fn futex_wait_private(
+    a: &AtomicU32,
+    expected: u32,
+    timeout: Option<Duration>,
+) -> Result<WaitOutcome, Errno> {
+    wait_raw(
+        a,
+        libc::FUTEX_WAIT | libc::FUTEX_PRIVATE_FLAG,
+        expected,
+        timeout,
+    )
+}
+
+/// Like `wake`, for a private futex.
+pub fn wake_private(a: &AtomicU32, n: u32) -> Result<usize, Errno> {
+    wake_raw(a, libc::FUTEX_WAKE | libc::FUTEX_PRIVATE_FLAG, n)
+}
+
+/// Like `wake_all`, for a private futex.
+pub fn wake_all_private(a: &AtomicU32) -> Result<usize, Errno> {
+    wake_private(a, u32::MAX)
+}
+
+fn private_flag(private: bool) -> i32 {
+    if private {
+        libc::FUTEX_PRIVATE_FLAG
+    } else {
+        0
+    }
+}
+
+/// Wake up to `n` threads waiting on `a`, and move up to `requeue` of the
+/// remaining waiters to wait on `target` instead, without waking them.
+/// Returns the total number of threads woken up or requeued.
+///
+/// Fails with `EAGAIN` if `a` no longer contains `expected`.
+///
+/// This lets a condition variable hand its waiters over to the mutex one at a time,
+/// instead of waking all of them only for all but one to go back to sleep on the mutex.
+pub fn cmp_requeue(
+    a: &AtomicU32,
+    expected: u32,
+    n: u32,
+    requeue: u32,
+    target: &AtomicU32,
+) -> Result<usize, Errno> {
+    cmp_requeue_raw(a, expected, n, requeue, target, false)
+}
+
+/// Like `cmp_requeue`, for private futexes.
+pub fn cmp_requeue_private(
+    a: &AtomicU32,
+    expected: u32,
+    n: u32,
+    requeue: u32,
+    target: &AtomicU32,
+) -> Result<usize, Errno> {
+    cmp_requeue_raw(a, expected, n, requeue, target, true)
+}
+
+fn cmp_requeue_raw(
+    a: &AtomicU32,
+    expected: u32,
+    n: u32,
+    requeue: u32,
+    target: &AtomicU32,
+    private: bool,
+) -> Result<usize, Errno> {
+    let n = n.min(i32::MAX as u32);
+    let requeue = requeue.min(i32::MAX as u32);
+    // The timeout argument is used for the number of threads to requeue.
+    unsafe {
+        futex(
+            a,
+            libc::FUTEX_CMP_REQUEUE | private_flag(private),
+            n,
+            requeue as usize as *const libc::timespec,
+            target,
+            expected,
+        )
+    }
+    .map(|count| count as usize)
+}
+
+/// The operation `wake_op` applies to its second atomic.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeOpKind {
+    /// `b = oparg`
+    Set,
+    /// `b += oparg`
+    Add,
+    /// `b |= oparg`
+    Or,
+    /// `b &= !oparg`
+    AndNot,
+    /// `b ^= oparg`
+    Xor,
+}
+
+/// The comparison `wake_op` makes between the old value of its second atomic and `cmparg`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WakeOpCmp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// The operation and condition of `wake_op`.
+/// `oparg` and `cmparg` are limited to 12 bits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct WakeOp {
+    pub op: WakeOpKind,
+    pub oparg: u32,
+    pub cmp: WakeOpCmp,
+    pub cmparg: u32,
+}
+
+impl WakeOp {
+    fn encode(self) -> u32 {
+        assert!(self.oparg < 1 << 12, "oparg must fit in 12 bits");
+        assert!(self.cmparg < 1 << 12, "cmparg must fit in 12 bits");
+        let op = match self.op {
+            WakeOpKind::Set => libc::FUTEX_OP_SET,
+            WakeOpKind::Add => libc::FUTEX_OP_ADD,
+            WakeOpKind::Or => libc::FUTEX_OP_OR,
+            WakeOpKind::AndNot => libc::FUTEX_OP_ANDN,
+            WakeOpKind::Xor => libc::FUTEX_OP_XOR,
+        };
+        let cmp = match self.cmp {
+            WakeOpCmp::Eq => libc::FUTEX_OP_CMP_EQ,
+            WakeOpCmp::Ne => libc::FUTEX_OP_CMP_NE,
+            WakeOpCmp::Lt => libc::FUTEX_OP_CMP_LT,
+            WakeOpCmp::Le => libc::FUTEX_OP_CMP_LE,
+            WakeOpCmp::Gt => libc::FUTEX_OP_CMP_GT,
+            WakeOpCmp::Ge => libc::FUTEX_OP_CMP_GE,
+        };
+        (op as u32) << 28 | (cmp as u32) << 24 | self.oparg << 12 | self.cmparg
+    }
+}
+
+/// Atomically apply `op` to `b`, wake up to `n` threads waiting on `a`, and,
+/// if the old value of `b` passes the comparison of `op`, also wake up to `n2`
+/// threads waiting on `b`. Returns the total number of threads woken up.
+pub fn wake_op(a: &AtomicU32, n: u32, b: &AtomicU32, n2: u32, op: WakeOp) -> Result<usize, Errno> {
+    wake_op_raw(a, n, b, n2, op, false)
+}
+
+/// Like `wake_op`, for private futexes.
+pub fn wake_op_private(
+    a: &AtomicU32,
+    n: u32,
+    b: &AtomicU32,
+    n2: u32,
+    op: WakeOp,
+) -> Result<usize, Errno> {
+    wake_op_raw(a, n, b, n2, op, true)
+}
+
+fn wake_op_raw(
+    a: &AtomicU32,
+    n: u32,
+    b: &AtomicU32,
+    n2: u32,
+    op: WakeOp,
+    private: bool,
+) -> Result<usize, Errno> {
+    let n = n.min(i32::MAX as u32);
+    let n2 = n2.min(i32::MAX as u32);
+    // The timeout argument is used for the number of threads to wake on `b`.
+    unsafe {
+        futex(
+            a,
+            libc::FUTEX_WAKE_OP | private_flag(private),
+            n,
+            n2 as usize as *const libc::timespec,
+            b,
+            op.encode(),
+        )
+    }
+    .map(|woken| woken as usize)
+}
+
+/// An absolute point in time for the `_until` and `_bitset` waits.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Deadline {
+    /// Against `CLOCK_MONOTONIC`, which isn't affected by changes to the system time.
+    Monotonic(Instant),
+    /// Against `CLOCK_REALTIME`, the wall clock.
+    Realtime(SystemTime),
+}
+
+impl Deadline {
+    /// The timespec and the clock it is measured against.
+    fn to_timespec(self) -> (libc::timespec, libc::clockid_t) {
+        match self {
+            Deadline::Monotonic(deadline) => {
+                // Instant is opaque, so go through the current time on both clocks.
+                let mut now = libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: 0,
+                };
+                unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let now = Duration::new(now.tv_sec as u64, now.tv_nsec as u32);
+                (to_timespec(now + remaining), libc::CLOCK_MONOTONIC)
+            }
+            Deadline::Realtime(deadline) => {
+                let since_epoch = deadline.duration_since(UNIX_EPOCH).unwrap_or_default();
+                (to_timespec(since_epoch), libc::CLOCK_REALTIME)
+            }
+        }
+    }
+}
+
+/// Like `futex_wait`, but only woken up by `wake_bitset` calls whose bitset
+/// shares a bit with `bitset`, and with an absolute `deadline` instead of a timeout.
+pub fn futex_wait_bitset(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: Option<Deadline>,
+    bitset: u32,
+) -> Result<WaitOutcome, Errno> {
+    wait_bitset_raw(a, expected, deadline, bitset, false)
+}
+
+/// Like `futex_wait_bitset`, for a private futex.
+pub fn futex_wait_bitset_private(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: Option<Deadline>,
+    bitset: u32,
+) -> Result<WaitOutcome, Errno> {
+    wait_bitset_raw(a, expected, deadline, bitset, true)
+}
+
+/// Like `futex_wait`, but with an absolute `deadline` instead of a timeout.
+pub fn futex_wait_until(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: Option<Deadline>,
+) -> Result<WaitOutcome, Errno> {
+    futex_wait_bitset(a, expected, deadline, libc::FUTEX_BITSET_MATCH_ANY as u32)
+}
+
+/// Like `futex_wait_until`, for a private futex.
+pub fn futex_wait_until_private(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: Option<Deadline>,
+) -> Result<WaitOutcome, Errno> {
+    futex_wait_bitset_private(a, expected, deadline, libc::FUTEX_BITSET_MATCH_ANY as u32)
+}
+
+fn wait_bitset_raw(
+    a: &AtomicU32,
+    expected: u32,
+    deadline: Option<Deadline>,
+    bitset: u32,
+    private: bool,
+) -> Result<WaitOutcome, Errno> {
+    assert_ne!(bitset, 0, "the bitset must not be empty");
+    let (timeout, clock) = match deadline.map(Deadline::to_timespec) {
+        Some((t, libc::CLOCK_REALTIME)) => (Some(t), libc::FUTEX_CLOCK_REALTIME),
+        Some((t, _)) => (Some(t), 0),
+        None => (None, 0),
+    };
+    let timeout_ptr = timeout.as_ref().map_or(ptr::null(), |t| t as *const _);
+    let op = libc::FUTEX_WAIT_BITSET | clock | private_flag(private);
+    wait_outcome(unsafe { futex(a, op, expected, timeout_ptr, ptr::null(), bitset) })
+}
+
+/// Wake up to `n` threads waiting on `a` with a bitset that shares a bit with `bitset`.
+/// Returns the number of threads woken up.
+pub fn wake_bitset(a: &AtomicU32, n: u32, bitset: u32) -> Result<usize, Errno> {
+    wake_bitset_raw(a, n, bitset, false)
+}
+
+/// Like `wake_bitset`, for a private futex.
+pub fn wake_bitset_private(a: &AtomicU32, n: u32, bitset: u32) -> Result<usize, Errno> {
+    wake_bitset_raw(a, n, bitset, true)
+}
+
+fn wake_bitset_raw(a: &AtomicU32, n: u32, bitset: u32, private: bool) -> Result<usize, Errno> {
+    assert_ne!(bitset, 0, "the bitset must not be empty");
+    let n = n.min(i32::MAX as u32);
+    let op = libc::FUTEX_WAKE_BITSET | private_flag(private);
+    unsafe { futex(a, op, n, ptr::null(), ptr::null(), bitset) }.map(|woken| woken as usize)
+}
+
+/// Why `futex_waitv` returned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitvOutcome {
+    /// Woken up through the atomic at this index (or spuriously).
+    Woken(usize),
+    /// The deadline passed.
+    TimedOut,
+    /// One of the atomics didn't have the expected value, so we didn't go to sleep at all.
+    ValueMismatch,
+    /// Interrupted by a signal handler.
+    Interrupted,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WaitvError {
+    /// The kernel doesn't have `futex_waitv` (it was added in Linux 5.16).
+    /// The caller has to fall back to something else, like polling.
+    Unsupported,
+    Errno(Errno),
+}
+
+impl fmt::Display for WaitvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaitvError::Unsupported => f.write_str("futex_waitv is not supported by this kernel"),
+            WaitvError::Errno(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WaitvError {}
+
+/// `struct futex_waitv` from linux/futex.h
+#[repr(C)]
+struct FutexWaitv {
+    val: u64,
+    uaddr: u64,
+    flags: u32,
+    reserved: u32,
+}
+
+const FUTEX2_SIZE_U32: u32 = 0x02;
+const FUTEX2_PRIVATE: u32 = libc::FUTEX_PRIVATE_FLAG as u32;
+
+/// The maximum number of atomics `futex_waitv` can wait on at once.
+pub const FUTEX_WAITV_MAX: usize = 128;
+
+/// Sleep until woken up through any of the `(atomic, expected)` pairs, as long as
+/// each atomic contains its expected value, or until the `deadline`.
+/// Returns the index of the atomic we were woken up through.
+///
+/// Like all futex waits, it can also return spuriously.
+/// There can be at most `FUTEX_WAITV_MAX` atomics.
+pub fn futex_waitv(
+    futexes: &[(&AtomicU32, u32)],
+    deadline: Option<Deadline>,
+) -> Result<WaitvOutcome, WaitvError> {
+    waitv_raw(futexes, deadline, false)
+}
+
+/// Like `futex_waitv`, for private futexes.
+pub fn futex_waitv_private(
+    futexes: &[(&AtomicU32, u32)],
+    deadline: Option<Deadline>,
+) -> Result<WaitvOutcome, WaitvError> {
+    waitv_raw(futexes, deadline, true)
+}
+
+fn waitv_raw(
+    futexes: &[(&AtomicU32, u32)],
+    deadline: Option<Deadline>,
+    private: bool,
+) -> Result<WaitvOutcome, WaitvError> {
+    let flags = FUTEX2_SIZE_U32 | if private { FUTEX2_PRIVATE } else { 0 };
+    let waiters: Vec<FutexWaitv> = futexes
+        .iter()
+        .map(|&(a, expected)| FutexWaitv {
+            val: expected as u64,
+            uaddr: a as *const AtomicU32 as u64,
+            flags,
+            reserved: 0,
+        })
+        .collect();
+
+    // Unlike FUTEX_WAIT, futex_waitv takes the clock as a separate argument.
+    let (timeout, clock) = match deadline.map(Deadline::to_timespec) {
+        Some((t, clock)) => (Some(t), clock),
+        None => (None, libc::CLOCK_MONOTONIC),
+    };
+    let timeout_ptr = timeout.as_ref().map_or(ptr::null(), |t| t as *const _);
+
+    let r = unsafe {
+        libc::syscall(
+            libc::SYS_futex_waitv,
+            waiters.as_ptr(),
+            waiters.len() as libc::c_uint,
+            0 as libc::c_uint,
+            timeout_ptr,
+            clock,
+        )
+    };
+    if r >= 0 {
+        return Ok(WaitvOutcome::Woken(r as usize));
+    }
+    match Errno::last() {
+        Errno(libc::ETIMEDOUT) => Ok(WaitvOutcome::TimedOut),
+        Errno(libc::EAGAIN) => Ok(WaitvOutcome::ValueMismatch),
+        Errno(libc::EINTR) => Ok(WaitvOutcome::Interrupted),
+        Errno(libc::ENOSYS) => Err(WaitvError::Unsupported),
+        e => Err(WaitvError::Errno(e)),
+    }
+}
+
+/// For priority-inheritance futexes: set in the state word by the kernel when
+/// there are threads waiting in `lock_pi`.
+pub const FUTEX_WAITERS: u32 = 0x8000_0000;
+/// For priority-inheritance futexes: set by the kernel when the owner exited
+/// without unlocking.
+pub const FUTEX_OWNER_DIED: u32 = 0x4000_0000;
+/// For priority-inheritance futexes: the bits holding the thread id of the owner.
+pub const FUTEX_TID_MASK: u32 = 0x3fff_ffff;
+
+/// The kernel's id of the calling thread, as stored in priority-inheritance futexes.
+pub fn gettid() -> u32 {
+    unsafe { libc::gettid() as u32 }
+}
+
+// Priority inheritance
+//
+// The state word of a PI futex is 0 when unlocked, or the thread id of the owner,
+// possibly with the FUTEX_WAITERS and FUTEX_OWNER_DIED bits set. Uncontended locking
+// and unlocking is done in user space, with a compare-and-exchange from 0 to our tid
+// and back. Only when that fails, the kernel is asked to take over. While a thread
+// is blocked in `lock_pi`, the kernel boosts the priority of the owner to that of the
+// blocked thread, so a low priority owner can't be starved by medium priority threads.
+
+/// Block until we own the PI futex `a`. Fails with `EDEADLK` if we already own it.
+pub fn lock_pi(a: &AtomicU32) -> Result<(), Errno> {
+    pi_raw(a, libc::FUTEX_LOCK_PI)
+}
+
+/// Like `lock_pi`, for a private futex.
+pub fn lock_pi_private(a: &AtomicU32) -> Result<(), Errno> {
+    pi_raw(a, libc::FUTEX_LOCK_PI | libc::FUTEX_PRIVATE_FLAG)
+}
+
+/// Try to take the PI futex `a`, also when the user space fast path can't,
+/// e.g. because the owner died. Fails with `EAGAIN` if it is locked.
+pub fn trylock_pi(a: &AtomicU32) -> Result<(), Errno> {
+    pi_raw(a, libc::FUTEX_TRYLOCK_PI)
+}
+
+/// Like `trylock_pi`, for a private futex.
+pub fn trylock_pi_private(a: &AtomicU32) -> Result<(), Errno> {
+    pi_raw(a, libc::FUTEX_TRYLOCK_PI | libc::FUTEX_PRIVATE_FLAG)
+}
+
+/// Unlock the PI futex `a`, handing it over to the highest priority waiter.
+/// Fails with `EPERM` if we don't own it.
+pub fn unlock_pi(a: &AtomicU32) -> Result<(), Errno> {
+    pi_raw(a, libc::FUTEX_UNLOCK_PI)
+}
+
+/// Like `unlock_pi`, for a private futex.
+pub fn unlock_pi_private(a: &AtomicU32) -> Result<(), Errno> {
+    pi_raw(a, libc::FUTEX_UNLOCK_PI | libc::FUTEX_PRIVATE_FLAG)
+}
+
+fn pi_raw(a: &AtomicU32, op: i32) -> Result<(), Errno> {
+    // No timeout: FUTEX_LOCK_PI would take an absolute CLOCK_REALTIME one.
+    unsafe { futex(a, op, 0, ptr::null(), ptr::null(), 0) }.map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        sync::atomic::{AtomicBool, AtomicU64, Ordering},
+        thread,
+        time::Instant,
+    };
+
+    /// Keep waking `a` until `n` threads in total have been woken up.
+    fn wake_until(a: &AtomicU32, n: usize, wake: impl Fn(&AtomicU32) -> Result<usize, Errno>) {
+        let mut woken = 0;
+        while woken < n {
+            woken += wake(a).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(woken, n);
+    }
+
+    #[test]
+    fn value_mismatch() {
+        let a = AtomicU32::new(0);
+        assert_eq!(futex_wait(&a, 1, None), Ok(WaitOutcome::ValueMismatch));
+        assert_eq!(
+            futex_wait_private(&a, 1, None),
+            Ok(WaitOutcome::ValueMismatch)
+        );
+    }
+
+    #[test]
+    fn timed_out() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        assert_eq!(
+            futex_wait(&a, 0, Some(Duration::from_millis(20))),
+            Ok(WaitOutcome::TimedOut)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn wake_nobody() {
+        let a = AtomicU32::new(0);
+        assert_eq!(wake(&a, 1), Ok(0));
+        assert_eq!(wake_all_private(&a), Ok(0));
+    }
+
+    #[test]
+    fn woken() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            let t = s.spawn(|| futex_wait(&a, 0, None));
+            wake_until(&a, 1, |a| wake(a, 1));
+            assert_eq!(t.join().unwrap(), Ok(WaitOutcome::Woken));
+        });
+    }
+
+    #[test]
+    fn wake_all_wakes_every_waiter() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            let threads: Vec<_> = (0..4)
+                .map(|_| s.spawn(|| futex_wait_private(&a, 0, None)))
+                .collect();
+            wake_until(&a, 4, wake_all_private);
+            for t in threads {
+                assert_eq!(t.join().unwrap(), Ok(WaitOutcome::Woken));
+            }
+        });
+    }
+
+    #[test]
+    fn private_and_shared_do_not_mix() {
+        let a = AtomicU32::new(0);
+        let waiting = AtomicBool::new(false);
+        thread::scope(|s| {
+            let t = s.spawn(|| {
+                waiting.store(true, Ordering::Relaxed);
+                futex_wait_private(&a, 0, None)
+            });
+            while !waiting.load(Ordering::Relaxed) {
+                thread::yield_now();
+            }
+            thread::sleep(Duration::from_millis(10));
+            // A shared wake doesn't find the private waiter.
+            assert_eq!(wake_all(&a), Ok(0));
+            wake_until(&a, 1, |a| wake_private(a, 1));
+            assert_eq!(t.join().unwrap(), Ok(WaitOutcome::Woken));
+        });
+    }
+
+    #[test]
+    fn interrupted() {
+        extern "C" fn handler(_: libc::c_int) {}
+
+        // Without SA_RESTART, so the wait returns EINTR instead of being restarted.
+        // The handler is process-wide, so keep the previous one to restore it after.
+        let mut old_action: libc::sigaction = unsafe { std::mem::zeroed() };
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handler as extern "C" fn(libc::c_int) as libc::sighandler_t;
+            assert_eq!(libc::sigaction(libc::SIGUSR1, &action, &mut old_action), 0);
+        }
+
+        let a = AtomicU32::new(0);
+        let tid = AtomicU64::new(0);
+        let done = AtomicBool::new(false);
+        thread::scope(|s| {
+            s.spawn(|| {
+                tid.store(unsafe { libc::pthread_self() } as u64, Ordering::Relaxed);
+                // A signal that arrives before we start waiting is missed, so wait
+                // until one actually interrupts the wait.
+                loop {
+                    match futex_wait(&a, 0, None) {
+                        Ok(WaitOutcome::Interrupted) => break,
+                        Ok(WaitOutcome::Woken) => continue,
+                        other => panic!("unexpected {other:?}"),
+                    }
+                }
+                done.store(true, Ordering::Relaxed);
+            });
+            while !done.load(Ordering::Relaxed) {
+                let tid = tid.load(Ordering::Relaxed);
+                if tid != 0 {
+                    unsafe { libc::pthread_kill(tid as libc::pthread_t, libc::SIGUSR1) };
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+
+        // The thread we signaled has exited, so no signal for it is still pending.
+        unsafe {
+            assert_eq!(
+                libc::sigaction(libc::SIGUSR1, &old_action, ptr::null_mut()),
+                0
+            );
+        }
+    }
+
+    #[test]
+    fn cmp_requeue_moves_waiters() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(0);
+        assert_eq!(
+            cmp_requeue_private(&a, 1, 0, u32::MAX, &b),
+            Err(Errno(libc::EAGAIN))
+        );
+        thread::scope(|s| {
+            let threads: Vec<_> = (0..3)
+                .map(|_| s.spawn(|| futex_wait_private(&a, 0, None)))
+                .collect();
+            // Move the waiters to b without waking any of them.
+            wake_until(&a, 3, |a| cmp_requeue_private(a, 0, 0, u32::MAX, &b));
+            assert_eq!(wake_all_private(&a), Ok(0));
+            // Now they're woken through b, one at a time.
+            wake_until(&b, 3, |b| wake_private(b, 1));
+            for t in threads {
+                assert_eq!(t.join().unwrap(), Ok(WaitOutcome::Woken));
+            }
+        });
+    }
+
+    #[test]
+    fn wake_op_modifies_second_atomic() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(3);
+        let op = WakeOp {
+            op: WakeOpKind::Add,
+            oparg: 5,
+            cmp: WakeOpCmp::Eq,
+            cmparg: 3,
+        };
+        assert_eq!(wake_op(&a, 1, &b, 1, op), Ok(0));
+        assert_eq!(b.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn wake_op_wakes_second_atomic_if_condition_holds() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(0);
+        // Leaves b at 0, and wakes waiters on b if it was 0.
+        let op = WakeOp {
+            op: WakeOpKind::Set,
+            oparg: 0,
+            cmp: WakeOpCmp::Eq,
+            cmparg: 0,
+        };
+        let never = WakeOp { cmparg: 1, ..op };
+        thread::scope(|s| {
+            let t = s.spawn(|| futex_wait_private(&b, 0, None));
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(wake_op_private(&a, 1, &b, 1, never), Ok(0));
+            wake_until(&a, 1, |a| wake_op_private(a, 1, &b, 1, op));
+            assert_eq!(t.join().unwrap(), Ok(WaitOutcome::Woken));
+        });
+    }
+
+    #[test]
+    fn bitset() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            let t = s.spawn(|| futex_wait_bitset_private(&a, 0, None, 0b01));
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(wake_bitset_private(&a, u32::MAX, 0b10), Ok(0));
+            wake_until(&a, 1, |a| wake_bitset_private(a, u32::MAX, 0b11));
+            assert_eq!(t.join().unwrap(), Ok(WaitOutcome::Woken));
+        });
+    }
+
+    #[test]
+    fn wait_until_monotonic_deadline() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        let deadline = Deadline::Monotonic(start + Duration::from_millis(20));
+        assert_eq!(
+            futex_wait_until(&a, 0, Some(deadline)),
+            Ok(WaitOutcome::TimedOut)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn wait_until_realtime_deadline() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        let deadline = Deadline::Realtime(SystemTime::now() + Duration::from_millis(20));
+        assert_eq!(
+            futex_wait_until_private(&a, 0, Some(deadline)),
+            Ok(WaitOutcome::TimedOut)
+        );
+        assert!(start.elapsed() >= Duration::from_millis(15));
+
+        // A deadline in the past times out right away.
+        let past = Deadline::Realtime(SystemTime::now() - Duration::from_secs(1));
+        assert_eq!(
+            futex_wait_until(&a, 0, Some(past)),
+            Ok(WaitOutcome::TimedOut)
+        );
+    }
+
+    #[test]
+    fn waitv_value_mismatch() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(0);
+        match futex_waitv(&[(&a, 0), (&b, 1)], None) {
+            Err(WaitvError::Unsupported) => return,
+            r => assert_eq!(r, Ok(WaitvOutcome::ValueMismatch)),
+        }
+        assert!(matches!(
+            futex_waitv(&[], None),
+            Err(WaitvError::Errno(Errno(libc::EINVAL)))
+        ));
+    }
+
+    #[test]
+    fn waitv_timed_out() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(0);
+        let start = Instant::now();
+        let deadline = Deadline::Monotonic(start + Duration::from_millis(20));
+        match futex_waitv_private(&[(&a, 0), (&b, 0)], Some(deadline)) {
+            Err(WaitvError::Unsupported) => return,
+            r => assert_eq!(r, Ok(WaitvOutcome::TimedOut)),
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn waitv_reports_which_one_woke() {
+        let a = AtomicU32::new(0);
+        let b = AtomicU32::new(0);
+        if futex_waitv(&[(&a, 1)], None) == Err(WaitvError::Unsupported) {
+            return;
+        }
+        thread::scope(|s| {
+            let t = s.spawn(|| futex_waitv_private(&[(&a, 0), (&b, 0)], None));
+            wake_until(&b, 1, |b| wake_private(b, 1));
+            assert_eq!(t.join().unwrap(), Ok(WaitvOutcome::Woken(1)));
+        });
+    }
+
+    #[test]
+    fn lock_pi_stores_owner_tid() {
+        let a = AtomicU32::new(0);
+        assert_eq!(lock_pi_private(&a), Ok(()));
+        assert_eq!(a.load(Ordering::Relaxed), gettid());
+        assert_eq!(lock_pi_private(&a), Err(Errno(libc::EDEADLK)));
+        thread::scope(|s| {
+            s.spawn(|| {
+                assert_eq!(trylock_pi_private(&a), Err(Errno(libc::EAGAIN)));
+                assert_eq!(unlock_pi_private(&a), Err(Errno(libc::EPERM)));
+            });
+        });
+        assert_eq!(unlock_pi_private(&a), Ok(()));
+        assert_eq!(a.load(Ordering::Relaxed), 0);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::futex::{wait, wake_all};
+
+/// A reusable barrier: `n` threads block in `wait` until all of them arrived,
+/// after which the barrier can be used again for the next round.
+pub struct Barrier {
+    n: u32,
+    /// The number of threads that arrived in the current round
+    count: AtomicU32,
+    /// Incremented at the end of every round. Threads wait on this,
+    /// so that a thread that is already in the next round can't be
+    /// confused with one that is still waiting in the previous one.
+    generation: AtomicU32,
+}
+
+pub struct BarrierWaitResult {
+    is_leader: bool,
+}
+
+impl BarrierWaitResult {
+    /// Exactly one thread per round is the leader: the last one to arrive.
+    pub fn is_leader(&self) -> bool {
+        self.is_leader
+    }
+}
+
+impl Barrier {
+    pub const fn new(n: u32) -> Self {
+        Self {
+            n,
+            count: AtomicU32::new(0),
+            generation: AtomicU32::new(0),
+        }
+    }
+
+    pub fn wait(&self) -> BarrierWaitResult {
+        // Load the generation before arriving: the round can't end before we arrive.
+        let generation = self.generation.load(Ordering::Relaxed);
+
+        // AcqRel: the leader must see everything the other threads did before
+        // arriving, so it can pass it on to them through the generation.
+        if self.count.fetch_add(1, Ordering::AcqRel) + 1 >= self.n {
+            // Last to arrive: reset the count for the next round, and release everybody.
+            // The store can be Relaxed, since the threads of the next round all
+            // load the new generation first (or are the leader itself).
+            self.count.store(0, Ordering::Relaxed);
+            self.generation.fetch_add(1, Ordering::Release);
+            wake_all(&self.generation);
+            BarrierWaitResult { is_leader: true }
+        } else {
+            while self.generation.load(Ordering::Acquire) == generation {
+                wait(&self.generation, generation);
+            }
+            BarrierWaitResult { is_leader: false }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Barrier;
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn many_rounds() {
+        const THREADS: u32 = 8;
+        const ROUNDS: usize = 1000;
+
+        let barrier = Barrier::new(THREADS);
+        let arrived: Vec<AtomicU32> = (0..ROUNDS).map(|_| AtomicU32::new(0)).collect();
+        let leaders: Vec<AtomicU32> = (0..ROUNDS).map(|_| AtomicU32::new(0)).collect();
+
+        thread::scope(|s| {
+            for _ in 0..THREADS {
+                s.spawn(|| {
+                    for round in 0..ROUNDS {
+                        arrived[round].fetch_add(1, Ordering::Relaxed);
+                        if barrier.wait().is_leader() {
+                            leaders[round].fetch_add(1, Ordering::Relaxed);
+                        }
+                        // Nobody can leave the barrier before everybody arrived
+                        assert_eq!(arrived[round].load(Ordering::Relaxed), THREADS);
+                    }
+                });
+            }
+        });
+
+        for leader_count in &leaders {
+            assert_eq!(leader_count.load(Ordering::Relaxed), 1);
+        }
+    }
+
+    #[test]
+    fn single_thread_is_always_leader() {
+        let barrier = Barrier::new(1);
+        for _ in 0..10 {
+            assert!(barrier.wait().is_leader());
+        }
+    }
+}
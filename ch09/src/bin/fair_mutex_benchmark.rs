@@ -0,0 +1,65 @@
+use std::{
+    hint::black_box,
+    thread,
+    time::{Duration, Instant},
+};
+
+use ch09::{fair_mutex::FairMutex, mutex_v3};
+
+const THREADS: usize = 8;
+const RUN_TIME: Duration = Duration::from_secs(2);
+
+/// Let THREADS threads repeatedly call `lock_and_work` for RUN_TIME, and report
+/// how long each call took (lock acquisition + critical section) and how evenly
+/// the lock was distributed among the threads.
+fn run(name: &str, lock_and_work: impl Fn() + Sync) {
+    let start = Instant::now();
+
+    let results: Vec<(usize, Vec<Duration>)> = thread::scope(|s| {
+        let threads: Vec<_> = (0..THREADS)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut latencies = Vec::new();
+                    while start.elapsed() < RUN_TIME {
+                        let t = Instant::now();
+                        lock_and_work();
+                        latencies.push(t.elapsed());
+                    }
+                    (latencies.len(), latencies)
+                })
+            })
+            .collect();
+        threads.into_iter().map(|t| t.join().unwrap()).collect()
+    });
+
+    let counts: Vec<usize> = results.iter().map(|(count, _)| *count).collect();
+    let mut latencies: Vec<Duration> = results.into_iter().flat_map(|(_, l)| l).collect();
+    latencies.sort_unstable();
+
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+    println!(
+        "{name}: {} locks, per thread min {} max {}, p50 {:?}, p99 {:?}, p99.9 {:?}, max {:?}",
+        latencies.len(),
+        counts.iter().min().unwrap(),
+        counts.iter().max().unwrap(),
+        percentile(0.5),
+        percentile(0.99),
+        percentile(0.999),
+        latencies.last().unwrap(),
+    );
+}
+
+/// Some work to do while holding the lock, to create contention.
+fn critical_section(value: &mut u64) {
+    for _ in 0..100 {
+        *value = black_box(*value + 1);
+    }
+}
+
+fn main() {
+    let m = mutex_v3::Mutex::new(0);
+    run("mutex_v3", || critical_section(&mut m.lock()));
+
+    let m = FairMutex::new(0);
+    run("fair_mutex", || critical_section(&mut m.lock()));
+}
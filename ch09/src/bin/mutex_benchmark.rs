@@ -1,37 +1,480 @@
-use std::time::Instant;
+// Contention benchmark for the locks in this crate.
+//
+// Every combination of lock, thread count, critical section length and read
+// percentage is run separately: all threads lock the same lock `--iterations`
+// times, doing `--cs` units of work while holding it. `--reads` percent of those
+// only read, which the RwLocks do through `read`, and the mutexes by locking as
+// usual. For each run, we report the throughput
+// and the p50/p99 time it took to acquire the lock. With the `count-calls`
+// feature, we also report the number of wait/wake calls made by the ch09 locks:
+//
+//   cargo run --release -p ch09 --features count-calls --bin mutex_benchmark
+//
+// Usage:
+//   mutex_benchmark [--locks v1,v2,v3,...] [--threads 1,2,4,8] [--cs 0,100]
+//                   [--reads 0,90] [--iterations 100000] [--format text|csv|json]
 
-use ch09::{mutex_v1, mutex_v2, mutex_v3};
+use std::{
+    env,
+    hint::black_box,
+    ops::{Deref, DerefMut},
+    process,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
 
-fn main() {
-    let m = mutex_v1::Mutex::new(0);
+use ch09::{
+    byte_mutex::ByteMutex,
+    mutex_v1, mutex_v2, mutex_v3, rwlock,
+    spin::{Adaptive, ExponentialBackoff, SpinStrategy, YieldAfter},
+};
 
-    // Force the compiler to assume there may be more code accessing the mutex
-    std::hint::black_box(&m);
+/// The spin lock from chapter 4, for comparison.
+struct SpinLock<T> {
+    locked: AtomicBool,
+    value: std::cell::UnsafeCell<T>,
+}
 
-    let start = Instant::now();
+unsafe impl<T> Sync for SpinLock<T> where T: Send {}
 
-    for _ in 0..5_000_000 {
-        *m.lock() += 1;
+struct SpinLockGuard<'a, T> {
+    lock: &'a SpinLock<T>,
+}
+
+impl<T> SpinLock<T> {
+    const fn new(value: T) -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            value: std::cell::UnsafeCell::new(value),
+        }
     }
 
-    let duration = start.elapsed();
-    println!("v1: locked {} times in {:?}", *m.lock(), duration);
+    fn lock(&self) -> SpinLockGuard<'_, T> {
+        while self.locked.swap(true, Ordering::Acquire) {
+            std::hint::spin_loop();
+        }
+        SpinLockGuard { lock: self }
+    }
+}
 
-    let m = mutex_v2::Mutex::new(0);
-    std::hint::black_box(&m);
-    let start = Instant::now();
-    for _ in 0..5_000_000 {
-        *m.lock() += 1;
+impl<T> Deref for SpinLockGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.value.get() }
+    }
+}
+
+impl<T> DerefMut for SpinLockGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.value.get() }
+    }
+}
+
+impl<T> Drop for SpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
+
+/// A lock protecting a counter, with a uniform interface for the benchmark.
+trait BenchLock: Sync {
+    /// Lock, do `cs` units of work, and unlock. Only reads the counter if `!write`.
+    /// Returns how long it took to acquire the lock.
+    fn run(&self, cs: u32, write: bool) -> Duration;
+    fn value(&self) -> u64;
+}
+
+/// The work done inside the critical section
+fn work(value: &mut u64, cs: u32) {
+    *value += 1;
+    for _ in 0..cs {
+        *value = black_box(*value);
+    }
+}
+
+/// The work done inside a critical section that only reads
+fn read_work(value: &u64, cs: u32) {
+    for _ in 0..cs {
+        black_box(*value);
+    }
+}
+
+macro_rules! impl_bench_lock {
+    ($lock:ty, $method:ident, |$g:ident| $unwrap:expr) => {
+        impl_bench_lock!($lock, $method, $method, |$g| $unwrap);
+    };
+    ($lock:ty, $write:ident, $read:ident, |$g:ident| $unwrap:expr) => {
+        impl BenchLock for $lock {
+            fn run(&self, cs: u32, write: bool) -> Duration {
+                let start = Instant::now();
+                if write {
+                    let $g = self.$write();
+                    let mut guard = $unwrap;
+                    let latency = start.elapsed();
+                    work(&mut guard, cs);
+                    latency
+                } else {
+                    let $g = self.$read();
+                    let guard = $unwrap;
+                    let latency = start.elapsed();
+                    read_work(&guard, cs);
+                    latency
+                }
+            }
+
+            fn value(&self) -> u64 {
+                let $g = self.$read();
+                let guard = $unwrap;
+                *guard
+            }
+        }
+    };
+}
+
+impl_bench_lock!(mutex_v1::Mutex<u64>, lock, |g| g);
+impl_bench_lock!(mutex_v2::Mutex<u64>, lock, |g| g);
+impl_bench_lock!(rwlock::v1::RwLock<u64>, write, read, |g| g);
+impl_bench_lock!(rwlock::v2::RwLock<u64>, write, read, |g| g);
+impl_bench_lock!(rwlock::v3::RwLock<u64>, write, read, |g| g);
+impl_bench_lock!(SpinLock<u64>, lock, |g| g);
+impl_bench_lock!(ByteMutex<u64>, lock, |g| g);
+impl_bench_lock!(std::sync::Mutex<u64>, lock, |g| g.unwrap());
+
+impl<S: SpinStrategy + Sync> BenchLock for mutex_v3::Mutex<u64, S> {
+    fn run(&self, cs: u32, write: bool) -> Duration {
+        let start = Instant::now();
+        let mut guard = self.lock();
+        let latency = start.elapsed();
+        if write {
+            work(&mut guard, cs);
+        } else {
+            read_work(&guard, cs);
+        }
+        latency
+    }
+
+    fn value(&self) -> u64 {
+        *self.lock()
+    }
+}
+
+const LOCKS: &[&str] = &[
+    "v1",
+    "v2",
+    "v3",
+    "v3-backoff",
+    "v3-yield",
+    "v3-adaptive",
+    "byte",
+    "rwlock-v1",
+    "rwlock-v2",
+    "rwlock-v3",
+    "spinlock",
+    "std",
+];
+
+fn new_lock(name: &str) -> Box<dyn BenchLock> {
+    match name {
+        "v1" => Box::new(mutex_v1::Mutex::new(0)),
+        "v2" => Box::new(mutex_v2::Mutex::new(0)),
+        "v3" => Box::new(mutex_v3::Mutex::new(0)),
+        "v3-backoff" => Box::new(mutex_v3::Mutex::with_strategy(
+            0,
+            ExponentialBackoff::<1024>,
+        )),
+        "v3-yield" => Box::new(mutex_v3::Mutex::with_strategy(0, YieldAfter::<100, 10>)),
+        "v3-adaptive" => Box::new(mutex_v3::Mutex::with_strategy(0, Adaptive::new())),
+        "byte" => Box::new(ByteMutex::new(0)),
+        "rwlock-v1" => Box::new(rwlock::v1::RwLock::new(0)),
+        "rwlock-v2" => Box::new(rwlock::v2::RwLock::new(0)),
+        "rwlock-v3" => Box::new(rwlock::v3::RwLock::new(0)),
+        "spinlock" => Box::new(SpinLock::new(0)),
+        "std" => Box::new(std::sync::Mutex::new(0)),
+        _ => unreachable!("lock names are checked when parsing the arguments"),
+    }
+}
+
+struct Config {
+    locks: Vec<String>,
+    threads: Vec<usize>,
+    cs: Vec<u32>,
+    reads: Vec<u32>,
+    iterations: usize,
+    format: Format,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Csv,
+    Json,
+}
+
+struct Measurement {
+    lock: String,
+    threads: usize,
+    cs: u32,
+    reads: u32,
+    ops: u64,
+    elapsed: Duration,
+    p50: Duration,
+    p99: Duration,
+    /// (waits, wakes), if they are counted
+    calls: Option<(u64, u64)>,
+}
+
+impl Measurement {
+    fn throughput(&self) -> f64 {
+        self.ops as f64 / self.elapsed.as_secs_f64()
     }
-    let duration = start.elapsed();
-    println!("v2: locked {} times in {:?}", *m.lock(), duration);
+}
+
+/// Whether the wait and wake calls are counted, and reported.
+const COUNT_CALLS: bool = cfg!(feature = "count-calls");
+
+/// Run `f`, and count the wait and wake calls the ch09 locks made in the meantime.
+fn count_calls<R>(f: impl FnOnce() -> R) -> (R, Option<(u64, u64)>) {
+    #[cfg(feature = "count-calls")]
+    {
+        let before = ch09::futex::wait_wake_calls();
+        let result = f();
+        let calls = ch09::futex::wait_wake_calls() - before;
+        (result, Some((calls.waits, calls.wakes)))
+    }
+    #[cfg(not(feature = "count-calls"))]
+    (f(), None)
+}
+
+/// Whether the `i`th lock operation of a run with `reads` percent reads only reads.
+/// Spreads the reads evenly over the run.
+fn is_read(i: usize, reads: u32) -> bool {
+    let reads = reads as usize;
+    i * reads / 100 != (i + 1) * reads / 100
+}
+
+fn measure(name: &str, threads: usize, cs: u32, reads: u32, iterations: usize) -> Measurement {
+    let lock = new_lock(name);
+    let lock = lock.as_ref();
 
-    let m = mutex_v3::Mutex::new(0);
-    std::hint::black_box(&m);
     let start = Instant::now();
-    for _ in 0..5_000_000 {
-        *m.lock() += 1;
+    let (mut latencies, calls): (Vec<Duration>, _) = count_calls(|| {
+        thread::scope(|s| {
+            let handles: Vec<_> = (0..threads)
+                .map(|_| {
+                    s.spawn(move || {
+                        let mut latencies = Vec::with_capacity(iterations);
+                        for i in 0..iterations {
+                            latencies.push(lock.run(cs, !is_read(i, reads)));
+                        }
+                        latencies
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .flat_map(|h| h.join().unwrap())
+                .collect()
+        })
+    });
+    let elapsed = start.elapsed();
+
+    let ops = (threads * iterations) as u64;
+    let writes = (0..iterations).filter(|&i| !is_read(i, reads)).count() * threads;
+    assert_eq!(lock.value(), writes as u64, "{name} lost updates");
+
+    latencies.sort_unstable();
+    let percentile = |p: f64| latencies[((latencies.len() - 1) as f64 * p) as usize];
+
+    Measurement {
+        lock: name.to_string(),
+        threads,
+        cs,
+        reads,
+        ops,
+        elapsed,
+        p50: percentile(0.5),
+        p99: percentile(0.99),
+        calls,
+    }
+}
+
+fn print_header(format: Format) {
+    match format {
+        Format::Text => {
+            print!(
+                "{:<12} {:>7} {:>6} {:>6} {:>14} {:>10} {:>10}",
+                "lock", "threads", "cs", "reads", "ops/s", "p50 (ns)", "p99 (ns)"
+            );
+            if COUNT_CALLS {
+                print!(" {:>10} {:>10}", "waits", "wakes");
+            }
+            println!();
+        }
+        Format::Csv => {
+            print!("lock,threads,cs,reads,ops,elapsed_ns,ops_per_sec,p50_ns,p99_ns");
+            if COUNT_CALLS {
+                print!(",waits,wakes");
+            }
+            println!();
+        }
+        Format::Json => println!("["),
+    }
+}
+
+fn print_measurement(format: Format, m: &Measurement, first: bool) {
+    match format {
+        Format::Text => {
+            print!(
+                "{:<12} {:>7} {:>6} {:>5}% {:>14.0} {:>10} {:>10}",
+                m.lock,
+                m.threads,
+                m.cs,
+                m.reads,
+                m.throughput(),
+                m.p50.as_nanos(),
+                m.p99.as_nanos(),
+            );
+            if let Some((waits, wakes)) = m.calls {
+                print!(" {waits:>10} {wakes:>10}");
+            }
+            println!();
+        }
+        Format::Csv => {
+            print!(
+                "{},{},{},{},{},{},{:.0},{},{}",
+                m.lock,
+                m.threads,
+                m.cs,
+                m.reads,
+                m.ops,
+                m.elapsed.as_nanos(),
+                m.throughput(),
+                m.p50.as_nanos(),
+                m.p99.as_nanos(),
+            );
+            if let Some((waits, wakes)) = m.calls {
+                print!(",{waits},{wakes}");
+            }
+            println!();
+        }
+        Format::Json => {
+            print!(
+                "{}  {{\"lock\": \"{}\", \"threads\": {}, \"cs\": {}, \"reads\": {}, \"ops\": {}, \
+                 \"elapsed_ns\": {}, \"ops_per_sec\": {:.0}, \"p50_ns\": {}, \"p99_ns\": {}",
+                if first { "" } else { ",\n" },
+                m.lock,
+                m.threads,
+                m.cs,
+                m.reads,
+                m.ops,
+                m.elapsed.as_nanos(),
+                m.throughput(),
+                m.p50.as_nanos(),
+                m.p99.as_nanos(),
+            );
+            if let Some((waits, wakes)) = m.calls {
+                print!(", \"waits\": {waits}, \"wakes\": {wakes}");
+            }
+            print!("}}");
+        }
+    }
+}
+
+fn print_footer(format: Format) {
+    if format == Format::Json {
+        println!("\n]");
+    }
+}
+
+fn usage(error: &str) -> ! {
+    eprintln!("error: {error}");
+    eprintln!(
+        "usage: mutex_benchmark [--locks {}] [--threads 1,2,4,8] [--cs 0,100] \
+         [--reads 0,90] [--iterations 100000] [--format text|csv|json]",
+        LOCKS.join(",")
+    );
+    process::exit(2);
+}
+
+fn parse_list<T: std::str::FromStr>(flag: &str, value: &str) -> Vec<T> {
+    value
+        .split(',')
+        .map(|v| {
+            v.parse()
+                .unwrap_or_else(|_| usage(&format!("invalid value {v:?} for {flag}")))
+        })
+        .collect()
+}
+
+fn parse_args() -> Config {
+    let mut config = Config {
+        locks: LOCKS.iter().map(|l| l.to_string()).collect(),
+        threads: vec![1, 2, 4, 8],
+        cs: vec![0, 100],
+        reads: vec![0, 90],
+        iterations: 100_000,
+        format: Format::Text,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(flag) = args.next() {
+        let value = args
+            .next()
+            .unwrap_or_else(|| usage(&format!("missing value for {flag}")));
+        match flag.as_str() {
+            "--locks" => {
+                config.locks = parse_list(&flag, &value);
+                if let Some(l) = config.locks.iter().find(|l| !LOCKS.contains(&l.as_str())) {
+                    usage(&format!("unknown lock {l:?}"));
+                }
+            }
+            "--threads" => config.threads = parse_list(&flag, &value),
+            "--cs" => config.cs = parse_list(&flag, &value),
+            "--reads" => config.reads = parse_list(&flag, &value),
+            "--iterations" => {
+                config.iterations = value
+                    .parse()
+                    .unwrap_or_else(|_| usage(&format!("invalid value {value:?} for {flag}")))
+            }
+            "--format" => {
+                config.format = match value.as_str() {
+                    "text" => Format::Text,
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    _ => usage(&format!("unknown format {value:?}")),
+                }
+            }
+            _ => usage(&format!("unknown argument {flag:?}")),
+        }
+    }
+
+    if config.threads.contains(&0) || config.iterations == 0 {
+        usage("--threads and --iterations must be at least 1");
+    }
+    if config.reads.iter().any(|&r| r > 100) {
+        usage("--reads is a percentage, so at most 100");
+    }
+
+    config
+}
+
+fn main() {
+    let config = parse_args();
+
+    print_header(config.format);
+    let mut first = true;
+    for lock in &config.locks {
+        for &threads in &config.threads {
+            for &cs in &config.cs {
+                for &reads in &config.reads {
+                    let m = measure(lock, threads, cs, reads, config.iterations);
+                    print_measurement(config.format, &m, first);
+                    first = false;
+                }
+            }
+        }
     }
-    let duration = start.elapsed();
-    println!("v2: locked {} times in {:?}", *m.lock(), duration);
+    print_footer(config.format);
 }
@@ -0,0 +1,203 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU8, Ordering},
+};
+
+use crate::{
+    futex::{count_wait, count_wake},
+    parking_lot::{park, unpark_one},
+};
+
+const LOCKED: u8 = 1;
+/// Set when there are (or may be) threads parked on the mutex.
+const PARKED: u8 = 2;
+
+/// A mutex with a one-byte state, which blocks using the `parking_lot` module
+/// instead of a futex, like `parking_lot::Mutex`.
+pub struct ByteMutex<T> {
+    /// LOCKED and PARKED bits
+    state: AtomicU8,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for ByteMutex<T> where T: Send {}
+
+pub struct ByteMutexGuard<'a, T> {
+    mutex: &'a ByteMutex<T>,
+}
+
+impl<T> Deref for ByteMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for ByteMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> ByteMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU8::new(0), // unlocked at creation
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> ByteMutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(0, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            lock_contended(&self.state);
+        }
+        ByteMutexGuard { mutex: self }
+    }
+
+    pub fn try_lock(&self) -> Option<ByteMutexGuard<'_, T>> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        while s & LOCKED == 0 {
+            match self.state.compare_exchange_weak(
+                s,
+                s | LOCKED,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(ByteMutexGuard { mutex: self }),
+                Err(e) => s = e,
+            }
+        }
+        None
+    }
+}
+
+fn lock_contended(state: &AtomicU8) {
+    let mut spin_count = 0;
+    let mut s = state.load(Ordering::Relaxed);
+    loop {
+        // Not locked: take it, keeping the PARKED bit for the threads still parked.
+        // (So a thread can take the lock ahead of a parked one that was just unparked.)
+        if s & LOCKED == 0 {
+            match state.compare_exchange_weak(s, s | LOCKED, Ordering::Acquire, Ordering::Relaxed) {
+                Ok(_) => return,
+                Err(e) => s = e,
+            }
+            continue;
+        }
+
+        // Spin for a short time first, unless others are already parked.
+        if s & PARKED == 0 && spin_count < 100 {
+            spin_count += 1;
+            std::hint::spin_loop();
+            s = state.load(Ordering::Relaxed);
+            continue;
+        }
+
+        // Make sure the unlocking thread knows to unpark us.
+        if s & PARKED == 0 {
+            if let Err(e) =
+                state.compare_exchange_weak(s, s | PARKED, Ordering::Relaxed, Ordering::Relaxed)
+            {
+                s = e;
+                continue;
+            }
+        }
+
+        // Only park if it's still locked with the PARKED bit set. The check is done
+        // while holding the queue lock, and `unlock_contended` updates the state
+        // while holding it too, so the unpark can't be missed.
+        count_wait();
+        park(
+            state as *const AtomicU8 as usize,
+            || state.load(Ordering::Relaxed) == LOCKED | PARKED,
+            None,
+        );
+        spin_count = 0;
+        s = state.load(Ordering::Relaxed);
+    }
+}
+
+fn unlock_contended(state: &AtomicU8) {
+    count_wake();
+    unpark_one(state as *const AtomicU8 as usize, |result| {
+        // Unlock, and keep the PARKED bit only if other threads are still parked.
+        let new = if result.have_more_threads { PARKED } else { 0 };
+        state.store(new, Ordering::Release);
+    });
+}
+
+impl<T> Drop for ByteMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let state = &self.mutex.state;
+        if state
+            .compare_exchange(LOCKED, 0, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            unlock_contended(state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ByteMutex, PARKED};
+    use std::{mem::size_of, sync::atomic::Ordering, thread};
+
+    #[test]
+    fn one_byte() {
+        assert_eq!(size_of::<ByteMutex<()>>(), 1);
+        assert_eq!(size_of::<ByteMutex<u8>>(), 2);
+    }
+
+    #[test]
+    fn try_lock() {
+        let m = ByteMutex::new(0);
+        let g = m.try_lock().unwrap();
+        assert!(m.try_lock().is_none());
+        drop(g);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    fn mutual_exclusion() {
+        let m = ByteMutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *m.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(*m.lock(), 80_000);
+        // All parked threads were unparked, and the bit was cleared.
+        assert_eq!(m.state.load(Ordering::Relaxed) & PARKED, 0);
+    }
+
+    #[test]
+    fn long_critical_sections() {
+        // Long enough for the other threads to stop spinning and park.
+        let m = ByteMutex::new(Vec::new());
+        thread::scope(|s| {
+            for i in 0..4 {
+                let m = &m;
+                s.spawn(move || {
+                    for _ in 0..10 {
+                        let mut g = m.lock();
+                        g.push(i);
+                        thread::sleep(std::time::Duration::from_millis(1));
+                    }
+                });
+            }
+        });
+        assert_eq!(m.lock().len(), 40);
+        assert_eq!(m.state.load(Ordering::Relaxed) & PARKED, 0);
+    }
+}
@@ -0,0 +1,179 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+use crate::{
+    futex::{wait, wake_all, wake_one},
+    mutex_v3::MutexGuard,
+    spin::SpinStrategy,
+};
+
+pub struct Condvar {
+    /// Incremented on every notification, so that a waiting thread can detect
+    /// that something happened between unlocking the mutex and going to sleep.
+    counter: AtomicU32,
+    /// Number of threads currently waiting, used to skip the wake syscall
+    /// when nobody is waiting.
+    num_waiters: AtomicUsize,
+}
+
+impl Condvar {
+    pub const fn new() -> Self {
+        Self {
+            counter: AtomicU32::new(0),
+            num_waiters: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn notify_one(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            wake_one(&self.counter);
+        }
+    }
+
+    pub fn notify_all(&self) {
+        if self.num_waiters.load(Ordering::Relaxed) > 0 {
+            self.counter.fetch_add(1, Ordering::Relaxed);
+            wake_all(&self.counter);
+        }
+    }
+
+    /// Unlock the mutex, wait for a notification, and lock the mutex again.
+    ///
+    /// Like `std::sync::Condvar::wait`, this may return spuriously, so the
+    /// caller should check its condition in a loop.
+    pub fn wait<'a, T, S: SpinStrategy>(
+        &self,
+        guard: MutexGuard<'a, T, S>,
+    ) -> MutexGuard<'a, T, S> {
+        // Register as a waiter before unlocking the mutex. A notifying thread
+        // must lock the mutex to change the condition, so it will see this
+        // increment and not skip the wake call.
+        self.num_waiters.fetch_add(1, Ordering::Relaxed);
+
+        // Load the counter before unlocking, so that a notification that
+        // happens right after unlocking changes the counter and makes `wait`
+        // below return immediately: no lost wake-ups.
+        let counter_value = self.counter.load(Ordering::Relaxed);
+
+        // Unlock the mutex by dropping the guard, but remember the mutex
+        // to lock it again later.
+        let mutex = guard.mutex;
+        drop(guard);
+
+        // Wait, but only if the counter hasn't changed since unlocking.
+        wait(&self.counter, counter_value);
+
+        self.num_waiters.fetch_sub(1, Ordering::Relaxed);
+
+        mutex.lock()
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Condvar;
+    use crate::mutex_v3::Mutex;
+    use std::{thread, time::Duration};
+
+    #[test]
+    fn test_condvar() {
+        let mutex = Mutex::new(0);
+        let condvar = Condvar::new();
+
+        let mut wakeups = 0;
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                thread::sleep(Duration::from_secs(1));
+                *mutex.lock() = 123;
+                condvar.notify_one();
+            });
+
+            let mut m = mutex.lock();
+            while *m < 100 {
+                m = condvar.wait(m);
+                wakeups += 1;
+            }
+
+            assert_eq!(*m, 123);
+        });
+
+        // Check that the main thread actually did wait (not busy-loop),
+        // while still allowing for a few spurious wake ups.
+        assert!(wakeups < 10);
+    }
+
+    #[test]
+    fn no_lost_wakeups() {
+        // Two threads play ping-pong: one increments the counter when it is
+        // even, the other when it is odd. Each notification races with the
+        // other thread going to sleep, so if a notification could get lost,
+        // one of the rounds below would hang forever.
+        let mutex = Mutex::new(0u32);
+        let condvar = Condvar::new();
+        const ROUNDS: u32 = 1000;
+
+        let play = |parity: u32| {
+            for _ in 0..ROUNDS {
+                let mut m = mutex.lock();
+                while *m % 2 != parity {
+                    m = condvar.wait(m);
+                }
+                *m += 1;
+                drop(m);
+                condvar.notify_one();
+            }
+        };
+
+        thread::scope(|s| {
+            s.spawn(|| play(0));
+            s.spawn(|| play(1));
+        });
+
+        assert_eq!(*mutex.lock(), 2 * ROUNDS);
+    }
+
+    #[test]
+    fn notify_all_wakes_every_waiter() {
+        let mutex = Mutex::new(false);
+        let condvar = Condvar::new();
+        let woken = Mutex::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let mut ready = mutex.lock();
+                    while !*ready {
+                        ready = condvar.wait(ready);
+                    }
+                    *woken.lock() += 1;
+                });
+            }
+
+            thread::sleep(Duration::from_millis(100));
+            *mutex.lock() = true;
+            condvar.notify_all();
+        });
+
+        assert_eq!(*woken.lock(), 8);
+    }
+
+    #[test]
+    fn notify_without_waiters_is_a_no_op() {
+        let condvar = Condvar::new();
+        condvar.notify_one();
+        condvar.notify_all();
+
+        // Nobody was waiting, so the counter shouldn't have been touched.
+        assert_eq!(
+            condvar.counter.load(std::sync::atomic::Ordering::Relaxed),
+            0
+        );
+    }
+}
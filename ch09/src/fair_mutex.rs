@@ -0,0 +1,146 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+use ch07::cache_padded::CachePadded;
+
+use crate::futex::{wait, wake_all};
+
+/// A ticket lock: every thread takes a ticket number, and the lock is handed
+/// over in the order of the tickets. Unlike `mutex_v3::Mutex`, a thread that
+/// just unlocked cannot barge in again ahead of the threads already waiting.
+pub struct FairMutex<T> {
+    /// The next ticket to hand out
+    next_ticket: CachePadded<AtomicU32>,
+    /// The ticket of the thread that owns the lock (or may take it right away).
+    /// In a separate cache line, since waiters spin on it while new threads
+    /// take tickets.
+    now_serving: CachePadded<AtomicU32>,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for FairMutex<T> where T: Send {}
+
+pub struct FairMutexGuard<'a, T> {
+    mutex: &'a FairMutex<T>,
+}
+
+impl<T> Deref for FairMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for FairMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T> FairMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            next_ticket: CachePadded::new(AtomicU32::new(0)),
+            now_serving: CachePadded::new(AtomicU32::new(0)),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> FairMutexGuard<'_, T> {
+        // Tickets wrap around, which is fine as long as there are
+        // fewer than 2^32 threads waiting.
+        // SeqCst: see the comment in drop().
+        let ticket = self.next_ticket.fetch_add(1, Ordering::SeqCst);
+
+        let mut serving = self.now_serving.load(Ordering::SeqCst);
+        if serving != ticket {
+            // Spin for a short time, in case we're next in line and the lock
+            // is held only briefly.
+            let mut spin_count = 0;
+            while serving != ticket && spin_count < 100 {
+                spin_count += 1;
+                std::hint::spin_loop();
+                serving = self.now_serving.load(Ordering::Relaxed);
+            }
+
+            // Sleep until it's our turn. Every unlock wakes all waiters,
+            // all but one of which go back to sleep.
+            while serving != ticket {
+                wait(&self.now_serving, serving);
+                serving = self.now_serving.load(Ordering::Relaxed);
+            }
+        }
+
+        // Synchronize with the Release increment of the previous owner.
+        fence(Ordering::Acquire);
+        FairMutexGuard { mutex: self }
+    }
+}
+
+impl<T> Drop for FairMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let mutex = self.mutex;
+        let serving = mutex
+            .now_serving
+            .fetch_add(1, Ordering::SeqCst)
+            .wrapping_add(1);
+
+        // Only make the syscall if a ticket has been handed out after ours.
+        //
+        // This and lock() form a store-load pattern on two atomics
+        // (now_serving/next_ticket here, next_ticket/now_serving there), for which
+        // Acquire/Release is not enough: both sides could see the old value
+        // of the other atomic, and the waiter would never be woken up.
+        // With SeqCst, either we see the new ticket, or the waiter sees
+        // our increment and doesn't go to sleep.
+        if mutex.next_ticket.load(Ordering::SeqCst) != serving {
+            // We can't wake only the thread with the right ticket,
+            // so wake all of them.
+            wake_all(&mutex.now_serving);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FairMutex;
+    use std::{sync::atomic::Ordering, thread, time::Duration};
+
+    #[test]
+    fn mutual_exclusion() {
+        let m = FairMutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *m.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(*m.lock(), 80_000);
+    }
+
+    #[test]
+    fn lock_is_handed_over_in_fifo_order() {
+        let m = &FairMutex::new(Vec::new());
+        thread::scope(|s| {
+            let g = m.lock();
+
+            // Queue up the threads one by one, waiting until each one took its ticket.
+            for i in 1..=5 {
+                s.spawn(move || m.lock().push(i));
+                while m.next_ticket.load(Ordering::Relaxed) != i + 1 {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+
+            drop(g);
+        });
+        assert_eq!(*m.lock(), [1, 2, 3, 4, 5]);
+    }
+}
@@ -0,0 +1,115 @@
+use std::{sync::atomic::AtomicU32, time::Duration};
+
+use crate::wait_address::{Backend, WaitAddress};
+
+#[cfg(feature = "count-calls")]
+pub use counts::{wait_wake_calls, WaitWakeCalls};
+
+/// Counting the wait and wake calls made by all the primitives in this crate,
+/// for the benchmarks. Every count is an atomic increment on a shared cache line,
+/// so it's only compiled in with the `count-calls` feature.
+#[cfg(feature = "count-calls")]
+mod counts {
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    static WAIT_CALLS: AtomicU64 = AtomicU64::new(0);
+    static WAKE_CALLS: AtomicU64 = AtomicU64::new(0);
+
+    /// The number of calls to the wait and wake operations of the backend
+    /// (not necessarily syscalls: see the `wait_address` module).
+    #[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+    pub struct WaitWakeCalls {
+        pub waits: u64,
+        pub wakes: u64,
+    }
+
+    /// The number of wait and wake calls made so far, by all threads.
+    /// Subtract two snapshots to get the calls made in between.
+    pub fn wait_wake_calls() -> WaitWakeCalls {
+        WaitWakeCalls {
+            waits: WAIT_CALLS.load(Ordering::Relaxed),
+            wakes: WAKE_CALLS.load(Ordering::Relaxed),
+        }
+    }
+
+    impl std::ops::Sub for WaitWakeCalls {
+        type Output = Self;
+
+        fn sub(self, rhs: Self) -> Self {
+            Self {
+                waits: self.waits - rhs.waits,
+                wakes: self.wakes - rhs.wakes,
+            }
+        }
+    }
+
+    pub(crate) fn count_wait() {
+        WAIT_CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn count_wake() {
+        WAKE_CALLS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Count a wait call, with the `count-calls` feature.
+/// Primitives that don't go through the functions below call this themselves.
+#[inline]
+pub(crate) fn count_wait() {
+    #[cfg(feature = "count-calls")]
+    counts::count_wait();
+}
+
+/// Count a wake call, with the `count-calls` feature.
+#[inline]
+pub(crate) fn count_wake() {
+    #[cfg(feature = "count-calls")]
+    counts::count_wake();
+}
+
+// All primitives go through these, so they can be counted, and so the backend
+// can be switched. See the `wait_address` module.
+
+pub(crate) fn wait(a: &AtomicU32, expected: u32) {
+    count_wait();
+    Backend::wait(a, expected, None);
+}
+
+/// Like `wait`, but gives up after `timeout`.
+///
+/// May also return early (spuriously), so the caller should re-check
+/// the atomic variable and the remaining time in a loop.
+pub(crate) fn wait_timeout(a: &AtomicU32, expected: u32, timeout: Duration) {
+    count_wait();
+    Backend::wait(a, expected, Some(timeout));
+}
+
+pub(crate) fn wake_one(a: &AtomicU32) {
+    count_wake();
+    Backend::wake_one(a);
+}
+
+pub(crate) fn wake_all(a: &AtomicU32) {
+    count_wake();
+    Backend::wake_all(a);
+}
+
+#[cfg(all(test, feature = "count-calls"))]
+mod tests {
+    use super::wait_wake_calls;
+    use std::sync::atomic::AtomicU32;
+
+    #[test]
+    fn counts_calls() {
+        let a = AtomicU32::new(0);
+        let before = wait_wake_calls();
+        super::wake_one(&a);
+        super::wake_all(&a);
+        super::wait(&a, 1); // Returns immediately: the value doesn't match
+        let diff = wait_wake_calls() - before;
+
+        // Other tests run in parallel and may make calls too.
+        assert!(diff.wakes >= 2);
+        assert!(diff.waits >= 1);
+    }
+}
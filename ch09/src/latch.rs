@@ -0,0 +1,103 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::futex::{wait, wake_all};
+
+/// A one-shot latch: threads block in `wait` until `count_down` has been called
+/// `count` times. Unlike a barrier, it can't be reused.
+pub struct CountDownLatch {
+    count: AtomicU32,
+}
+
+impl CountDownLatch {
+    pub const fn new(count: u32) -> Self {
+        Self {
+            count: AtomicU32::new(count),
+        }
+    }
+
+    pub fn count(&self) -> u32 {
+        self.count.load(Ordering::Relaxed)
+    }
+
+    /// Decrement the count, releasing all waiting threads when it reaches zero.
+    /// Does nothing if the count is already zero.
+    pub fn count_down(&self) {
+        // Release: everything done before counting down is visible to the waiting threads.
+        let previous = self
+            .count
+            .fetch_update(Ordering::Release, Ordering::Relaxed, |c| c.checked_sub(1));
+        if previous == Ok(1) {
+            wake_all(&self.count);
+        }
+    }
+
+    /// Block until the count reaches zero.
+    pub fn wait(&self) {
+        loop {
+            let c = self.count.load(Ordering::Acquire);
+            if c == 0 {
+                return;
+            }
+            wait(&self.count, c);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CountDownLatch;
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        thread,
+    };
+
+    #[test]
+    fn wait_for_workers() {
+        const WORKERS: u32 = 16;
+        let latch = CountDownLatch::new(WORKERS);
+        let done = AtomicU32::new(0);
+
+        thread::scope(|s| {
+            for _ in 0..WORKERS {
+                s.spawn(|| {
+                    done.fetch_add(1, Ordering::Relaxed);
+                    latch.count_down();
+                });
+            }
+
+            // Several threads waiting on the same latch
+            for _ in 0..4 {
+                s.spawn(|| {
+                    latch.wait();
+                    assert_eq!(done.load(Ordering::Relaxed), WORKERS);
+                });
+            }
+
+            latch.wait();
+            assert_eq!(done.load(Ordering::Relaxed), WORKERS);
+        });
+    }
+
+    #[test]
+    fn count_down_saturates_at_zero() {
+        let latch = CountDownLatch::new(1);
+        latch.count_down();
+        latch.count_down();
+        assert_eq!(latch.count(), 0);
+        latch.wait();
+    }
+
+    #[test]
+    fn many_rounds() {
+        // A fresh latch per round, to catch lost wake-ups.
+        for _ in 0..1000 {
+            let latch = CountDownLatch::new(2);
+            thread::scope(|s| {
+                s.spawn(|| latch.count_down());
+                s.spawn(|| latch.count_down());
+                s.spawn(|| latch.wait());
+                latch.wait();
+            });
+        }
+    }
+}
@@ -1,3 +1,20 @@
 pub mod mutex_v1;
 pub mod mutex_v2;
 pub mod mutex_v3;
+pub mod condvar;
+pub mod rwlock;
+pub mod futex;
+pub mod poison;
+pub mod fair_mutex;
+pub mod reentrant_mutex;
+pub mod semaphore;
+pub mod barrier;
+pub mod latch;
+pub mod spin;
+#[cfg(target_os = "linux")]
+pub mod pi_mutex;
+#[cfg(target_os = "linux")]
+pub mod shared_mutex;
+pub mod wait_address;
+pub mod parking_lot;
+pub mod byte_mutex;
@@ -4,7 +4,7 @@ use std::{
     sync::atomic::{AtomicU32, Ordering},
 };
 
-use atomic_wait::{wait, wake_one};
+use crate::futex::{wait, wake_one};
 
 pub struct Mutex<T> {
     /// 0: unlocked, 1: locked
@@ -40,7 +40,7 @@ impl<T> Mutex<T> {
         }
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T> {
         while self.state.swap(1, Ordering::Acquire) == 1 {
             wait(&self.state, 1);
         }
@@ -1,26 +1,111 @@
 use std::{
     cell::UnsafeCell,
+    marker::PhantomData,
+    mem,
     ops::{Deref, DerefMut},
-    sync::atomic::{AtomicU32, Ordering},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 
-use atomic_wait::{wait, wake_one};
+use crate::{
+    futex::{wait, wait_timeout, wake_one},
+    spin::{FixedSpin, SpinStrategy},
+};
 
-pub struct Mutex<T> {
+/// `S` decides how to spin before going to sleep when the lock is contended.
+/// See the `spin` module for the options.
+pub struct Mutex<T, S = FixedSpin> {
     /// 0: unlocked,
     /// 1: locked, no other threads waiting
     /// 2: locked, other threads waiting
     state: AtomicU32,
+    spin: S,
     value: UnsafeCell<T>,
 }
 
-unsafe impl<T> Sync for Mutex<T> where T: Send {}
+unsafe impl<T, S> Sync for Mutex<T, S>
+where
+    T: Send,
+    S: Sync,
+{
+}
+
+pub struct MutexGuard<'a, T, S = FixedSpin> {
+    pub(crate) mutex: &'a Mutex<T, S>,
+}
+
+impl<T, S> Deref for MutexGuard<'_, T, S> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T, S> DerefMut for MutexGuard<'_, T, S> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+/// A guard to a part of the data protected by a `Mutex`, created with `MutexGuard::map`.
+pub struct MappedMutexGuard<'a, U> {
+    state: &'a AtomicU32,
+    value: *mut U,
+    // Behave like the &'a mut U we're pointing to
+    _marker: PhantomData<&'a mut U>,
+}
+
+// Same as &mut U
+unsafe impl<U> Send for MappedMutexGuard<'_, U> where U: Send {}
+unsafe impl<U> Sync for MappedMutexGuard<'_, U> where U: Sync {}
+
+impl<U> Deref for MappedMutexGuard<'_, U> {
+    type Target = U;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.value }
+    }
+}
+
+impl<U> DerefMut for MappedMutexGuard<'_, U> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.value }
+    }
+}
+
+/// A guard that keeps the `Mutex` alive through an `Arc`, created with `Mutex::lock_arc`.
+/// Unlike `MutexGuard`, it does not borrow the mutex, so it can be `'static`
+/// and be moved into a spawned thread.
+///
+/// Sharing the guard between threads shares the `T`, so that needs `T: Sync`:
+///
+/// ```compile_fail
+/// use ch09::mutex_v3::Mutex;
+/// use std::{cell::Cell, sync::Arc, thread};
+///
+/// let guard = Arc::new(Mutex::new(Cell::new(0))).lock_arc();
+/// thread::scope(|s| {
+///     s.spawn(|| guard.set(1));
+///     guard.set(2);
+/// });
+/// ```
+pub struct ArcMutexGuard<T, S = FixedSpin> {
+    mutex: Arc<Mutex<T, S>>,
+}
 
-pub struct MutexGuard<'a, T> {
-    mutex: &'a Mutex<T>,
+// Same as &mut T. (Without this, it would be Sync whenever the mutex is.)
+unsafe impl<T, S> Sync for ArcMutexGuard<T, S>
+where
+    T: Sync,
+    S: Sync,
+{
 }
 
-impl<T> Deref for MutexGuard<'_, T> {
+impl<T, S> Deref for ArcMutexGuard<T, S> {
     type Target = T;
 
     fn deref(&self) -> &Self::Target {
@@ -28,43 +113,132 @@ impl<T> Deref for MutexGuard<'_, T> {
     }
 }
 
-impl<T> DerefMut for MutexGuard<'_, T> {
+impl<T, S> DerefMut for ArcMutexGuard<T, S> {
     fn deref_mut(&mut self) -> &mut Self::Target {
         unsafe { &mut *self.mutex.value.get() }
     }
 }
 
+impl<'a, T, S> MutexGuard<'a, T, S> {
+    // Associated functions rather than methods, so that they don't shadow
+    // methods of T called through Deref. Use as `MutexGuard::map(guard, ...)`.
+
+    /// Turn the guard into a guard to a part of the locked data, e.g. one field.
+    /// The mutex stays locked until the returned guard is dropped.
+    pub fn map<U>(guard: Self, f: impl FnOnce(&mut T) -> &mut U) -> MappedMutexGuard<'a, U> {
+        let mutex = guard.mutex;
+        // If f panics, guard is dropped normally and unlocks the mutex.
+        let value: *mut U = f(unsafe { &mut *mutex.value.get() });
+        // The mapped guard takes over the responsibility of unlocking.
+        mem::forget(guard);
+        MappedMutexGuard {
+            state: &mutex.state,
+            value,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Like `map`, but `f` may fail, in which case the original guard is given back.
+    pub fn try_map<U>(
+        guard: Self,
+        f: impl FnOnce(&mut T) -> Option<&mut U>,
+    ) -> Result<MappedMutexGuard<'a, U>, Self> {
+        let mutex = guard.mutex;
+        match f(unsafe { &mut *mutex.value.get() }) {
+            Some(value) => {
+                let value: *mut U = value;
+                mem::forget(guard);
+                Ok(MappedMutexGuard {
+                    state: &mutex.state,
+                    value,
+                    _marker: PhantomData,
+                })
+            }
+            None => Err(guard),
+        }
+    }
+}
+
 impl<T> Mutex<T> {
     pub const fn new(value: T) -> Self {
+        Self::with_strategy(value, FixedSpin)
+    }
+}
+
+impl<T, S: SpinStrategy> Mutex<T, S> {
+    pub const fn with_strategy(value: T, spin: S) -> Self {
         Self {
             state: AtomicU32::new(0), // unlocked at creation
+            spin,
             value: UnsafeCell::new(value),
         }
     }
 
-    pub fn lock(&self) -> MutexGuard<T> {
+    pub fn lock(&self) -> MutexGuard<'_, T, S> {
+        lock(&self.state, &self.spin);
+        MutexGuard { mutex: self }
+    }
+
+    /// Lock the mutex, returning a guard that owns a reference count of the `Arc`
+    /// instead of borrowing the mutex.
+    pub fn lock_arc(self: &Arc<Self>) -> ArcMutexGuard<T, S> {
+        lock(&self.state, &self.spin);
+        ArcMutexGuard {
+            mutex: Arc::clone(self),
+        }
+    }
+
+    /// Lock the mutex if it is unlocked, without blocking.
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T, S>> {
+        self.state
+            .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .ok()?;
+        Some(MutexGuard { mutex: self })
+    }
+
+    /// Lock the mutex, blocking for at most `timeout`.
+    /// Returns `None` if the lock could not be acquired in time.
+    pub fn lock_timeout(&self, timeout: Duration) -> Option<MutexGuard<'_, T, S>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => self.lock_until(deadline),
+            // The deadline is so far away that we can just as well wait forever
+            None => Some(self.lock()),
+        }
+    }
+
+    /// Lock the mutex, blocking until `deadline` at the latest.
+    /// Returns `None` if the lock could not be acquired in time.
+    pub fn lock_until(&self, deadline: Instant) -> Option<MutexGuard<'_, T, S>> {
         if self
             .state
             .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
             .is_err()
+            && !lock_contended_until(&self.state, &self.spin, deadline)
         {
-            lock_contended(&self.state); // Now we have the lock and state = 2.
-        } else {
-            // Success: The state was 0 before. We have now acquired the lock and set
-            // state to 1.
+            return None;
         }
 
-        MutexGuard { mutex: self }
+        Some(MutexGuard { mutex: self })
     }
 }
 
-fn lock_contended(state: &AtomicU32) {
-    // spin for a short time, in case the contension is low
-    let mut spin_count = 0;
-    while state.load(Ordering::Relaxed) == 1 && spin_count < 100 {
-        spin_count += 1;
-        std::hint::spin_loop();
+/// Lock the mutex with the given state, without creating a guard.
+/// Shared with the other mutexes built on the same state machine.
+pub(crate) fn lock(state: &AtomicU32, spin: &impl SpinStrategy) {
+    if state
+        .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        lock_contended(state, spin); // Now we have the lock and state = 2.
+    } else {
+        // Success: The state was 0 before. We have now acquired the lock and set
+        // state to 1.
     }
+}
+
+fn lock_contended(state: &AtomicU32, spin: &impl SpinStrategy) {
+    // spin for a short time, in case the contension is low
+    spin.spin(state);
 
     // Check after spinning, in case the lock is free now
     if state
@@ -80,18 +254,245 @@ fn lock_contended(state: &AtomicU32) {
     }
 }
 
-impl<T> Drop for MutexGuard<'_, T> {
+/// Same as `lock_contended`, but gives up at `deadline`.
+/// Returns whether the lock was acquired.
+fn lock_contended_until(state: &AtomicU32, spin: &impl SpinStrategy, deadline: Instant) -> bool {
+    spin.spin(state);
+
+    if state
+        .compare_exchange(0, 1, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok()
+    {
+        return true;
+    }
+
+    while state.swap(2, Ordering::Acquire) != 0 {
+        let now = Instant::now();
+        if now >= deadline {
+            // Giving up leaves the state at 2 even if we were the only waiter.
+            // That's fine: it only costs the owner an unnecessary wake_one call.
+            return false;
+        }
+        wait_timeout(state, 2, deadline - now);
+    }
+    true
+}
+
+pub(crate) fn unlock(state: &AtomicU32) {
+    // Swap with the current state (1 or 2) with 0
+    // to release the lock.
+    if state.swap(0, Ordering::Release) == 2 {
+        // Previous state was 2: Some other threads are waiting for the lock
+        // Only in this case we call wake_one.
+        wake_one(state);
+
+        // The state has been set to 0, so any other waiting thread should
+        // set the state back to 2 after waiting the lock in order not to forget other threads.
+        // See (1)
+    }
+}
+
+impl<T, S> Drop for MutexGuard<'_, T, S> {
+    fn drop(&mut self) {
+        unlock(&self.mutex.state);
+    }
+}
+
+impl<U> Drop for MappedMutexGuard<'_, U> {
     fn drop(&mut self) {
-        // Swap with the current state (1 or 2) with 0
-        // to release the lock.
-        if self.mutex.state.swap(0, Ordering::Release) == 2 {
-            // Previous state was 2: Some other threads are waiting for the lock
-            // Only in this case we call wake_one.
-            wake_one(&self.mutex.state);
-
-            // The state has been set to 0, so any other waiting thread should
-            // set the state back to 2 after waiting the lock in order not to forget other threads.
-            // See (1)
+        unlock(self.state);
+    }
+}
+
+impl<T, S> Drop for ArcMutexGuard<T, S> {
+    fn drop(&mut self) {
+        unlock(&self.mutex.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Mutex, MutexGuard};
+    use crate::spin::{Adaptive, ExponentialBackoff, FixedSpin, SpinStrategy, YieldAfter};
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn try_lock() {
+        let m = Mutex::new(0);
+        let g = m.try_lock().unwrap();
+        assert!(m.try_lock().is_none());
+        drop(g);
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    fn lock_timeout_gives_up_under_contention() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            let g = m.lock();
+            s.spawn(|| {
+                let start = Instant::now();
+                assert!(m.lock_timeout(Duration::from_millis(100)).is_none());
+                let elapsed = start.elapsed();
+                assert!(elapsed >= Duration::from_millis(100));
+                assert!(elapsed < Duration::from_millis(900));
+            })
+            .join()
+            .unwrap();
+            drop(g);
+        });
+    }
+
+    #[test]
+    fn lock_timeout_succeeds_when_released_in_time() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            let mut g = m.lock();
+            let t = s.spawn(|| {
+                let g = m.lock_timeout(Duration::from_secs(10)).unwrap();
+                *g
+            });
+            thread::sleep(Duration::from_millis(100));
+            *g = 42;
+            drop(g);
+            assert_eq!(t.join().unwrap(), 42);
+        });
+    }
+
+    #[test]
+    fn lock_until_past_deadline() {
+        let m = Mutex::new(0);
+        let _g = m.lock();
+        assert!(m.lock_until(Instant::now()).is_none());
+    }
+
+    #[test]
+    fn lock_timeout_many_threads() {
+        // Threads that time out must not break the lock for the others.
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    let mut acquired = 0;
+                    while acquired < 1000 {
+                        if let Some(mut g) = m.lock_timeout(Duration::from_micros(10)) {
+                            *g += 1;
+                            acquired += 1;
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(*m.lock(), 8000);
+    }
+
+    #[test]
+    fn map_to_field() {
+        struct Point {
+            x: i32,
+            y: i32,
+        }
+        let m = Mutex::new(Point { x: 1, y: 2 });
+
+        let mut y = MutexGuard::map(m.lock(), |p| &mut p.y);
+        *y += 10;
+        // Still locked through the mapped guard
+        assert!(m.try_lock().is_none());
+        drop(y);
+
+        let p = m.lock();
+        assert_eq!((p.x, p.y), (1, 12));
+    }
+
+    #[test]
+    fn try_map() {
+        let m = Mutex::new(vec![1, 2, 3]);
+
+        match MutexGuard::try_map(m.lock(), |v| v.get_mut(10)) {
+            Ok(_) => panic!("there's no element 10"),
+            Err(g) => assert_eq!(*g, [1, 2, 3]),
         }
+
+        let mut first = MutexGuard::try_map(m.lock(), |v| v.first_mut())
+            .ok()
+            .unwrap();
+        *first = 100;
+        drop(first);
+
+        assert_eq!(*m.lock(), [100, 2, 3]);
+    }
+
+    #[test]
+    fn map_panic_unlocks() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| MutexGuard::map(m.lock(), |_| -> &mut i32 { panic!() }))
+                .join();
+            assert!(result.is_err());
+        });
+        assert!(m.try_lock().is_some());
+    }
+
+    #[test]
+    fn lock_arc_moved_into_thread() {
+        let m = Arc::new(Mutex::new(0));
+        let mut g = m.lock_arc();
+        *g += 1;
+
+        // The guard is 'static, so it can be moved into a non-scoped thread.
+        let t = thread::spawn(move || {
+            *g += 1;
+            // Unlocked here, in the other thread.
+        });
+        t.join().unwrap();
+
+        assert_eq!(*m.lock(), 2);
+    }
+
+    #[test]
+    fn lock_arc_contended() {
+        let m = Arc::new(Mutex::new(0));
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let m = m.clone();
+                thread::spawn(move || {
+                    for _ in 0..10_000 {
+                        *m.lock_arc() += 1;
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(*m.lock(), 40_000);
+    }
+
+    #[test]
+    fn spin_strategies() {
+        fn check<S: SpinStrategy + Sync>(spin: S) {
+            let m = Mutex::with_strategy(0, spin);
+            thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..10_000 {
+                            *m.lock() += 1;
+                        }
+                    });
+                }
+            });
+            assert_eq!(*m.lock(), 40_000);
+        }
+
+        check(FixedSpin::<0>);
+        check(FixedSpin::<100>);
+        check(ExponentialBackoff::<1024>);
+        check(YieldAfter::<10, 10>);
+        check(Adaptive::new());
     }
 }
@@ -0,0 +1,280 @@
+//! A global table of queues of parked threads, keyed by address, like the one in
+//! the `parking_lot` crate.
+//!
+//! Unlike a futex, this doesn't need a 32-bit atomic to wait on: the address is
+//! just a key, and `validate` can check any condition before going to sleep.
+//! That allows locks with a one-byte state, like `ByteMutex`.
+
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, MutexGuard, PoisonError,
+    },
+    thread::{self, Thread},
+    time::{Duration, Instant},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParkResult {
+    /// Woken up by `unpark_one` or `unpark_all`.
+    Unparked,
+    /// `validate` returned false, so we didn't park.
+    Invalid,
+    /// The timeout expired.
+    TimedOut,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UnparkResult {
+    /// The number of threads that were unparked.
+    pub unparked_threads: usize,
+    /// Whether there are still threads parked on the same address.
+    pub have_more_threads: bool,
+}
+
+/// A parked thread.
+struct Parker {
+    thread: Thread,
+    /// Set when removed from the queue by an unpark operation.
+    unparked: AtomicBool,
+}
+
+struct Waiter {
+    address: usize,
+    parker: Arc<Parker>,
+}
+
+/// Multiple addresses share a bucket, so each waiter records its address.
+/// Per address, the waiters are in FIFO order.
+struct Bucket {
+    queue: Mutex<VecDeque<Waiter>>,
+}
+
+impl Bucket {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Waiter>> {
+        // Callbacks run while holding the lock, so it could get poisoned,
+        // but the queue itself is always in a consistent state.
+        self.queue.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// The number of shards. Threads parking on different addresses only contend
+/// on the same lock if the addresses end up in the same bucket.
+const BUCKETS: usize = 256;
+
+static BUCKET_TABLE: [Bucket; BUCKETS] = [const {
+    Bucket {
+        queue: Mutex::new(VecDeque::new()),
+    }
+}; BUCKETS];
+
+fn bucket(address: usize) -> &'static Bucket {
+    // Fibonacci hashing: multiply by 2^64 / golden ratio, and take the top bits.
+    let hash = (address as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+    &BUCKET_TABLE[(hash >> (64 - BUCKETS.trailing_zeros())) as usize]
+}
+
+/// Park the current thread on `address`, until unparked or until `timeout` expires.
+///
+/// `validate` is called while holding the queue lock of the address: if it returns
+/// false, we don't park. Since `unpark_one` and `unpark_all` take the same lock,
+/// a thread that changes the state and then unparks can't be missed.
+pub fn park(
+    address: usize,
+    validate: impl FnOnce() -> bool,
+    timeout: Option<Duration>,
+) -> ParkResult {
+    let deadline = timeout.and_then(|t| Instant::now().checked_add(t));
+    let bucket = bucket(address);
+    let parker = Arc::new(Parker {
+        thread: thread::current(),
+        unparked: AtomicBool::new(false),
+    });
+
+    {
+        let mut queue = bucket.lock();
+        if !validate() {
+            return ParkResult::Invalid;
+        }
+        queue.push_back(Waiter {
+            address,
+            parker: parker.clone(),
+        });
+    }
+
+    // thread::park() can return spuriously, so check the flag.
+    while !parker.unparked.load(Ordering::Acquire) {
+        match deadline {
+            None => thread::park(),
+            Some(deadline) => {
+                let now = Instant::now();
+                if now >= deadline {
+                    let mut queue = bucket.lock();
+                    // An unpark operation might have removed us just now.
+                    if parker.unparked.load(Ordering::Acquire) {
+                        break;
+                    }
+                    queue.retain(|w| !Arc::ptr_eq(&w.parker, &parker));
+                    return ParkResult::TimedOut;
+                }
+                thread::park_timeout(deadline - now);
+            }
+        }
+    }
+    ParkResult::Unparked
+}
+
+/// Unpark the thread that has been parked on `address` the longest, if any.
+///
+/// `callback` is called with the result while still holding the queue lock,
+/// before the thread is woken up, so it can update the state (e.g. clear a
+/// "parked" bit if there are no more threads) without racing with `park`.
+pub fn unpark_one(address: usize, callback: impl FnOnce(UnparkResult)) -> UnparkResult {
+    let mut queue = bucket(address).lock();
+    let mut unparked = None;
+    if let Some(i) = queue.iter().position(|w| w.address == address) {
+        unparked = queue.remove(i);
+    }
+    let result = UnparkResult {
+        unparked_threads: unparked.iter().count(),
+        have_more_threads: queue.iter().any(|w| w.address == address),
+    };
+    callback(result);
+    drop(queue);
+
+    if let Some(waiter) = unparked {
+        wake(waiter);
+    }
+    result
+}
+
+/// Unpark all threads parked on `address`.
+///
+/// Like `unpark_one`, `callback` is called while still holding the queue lock.
+pub fn unpark_all(address: usize, callback: impl FnOnce(UnparkResult)) -> UnparkResult {
+    let mut queue = bucket(address).lock();
+    let (unparked, others): (VecDeque<Waiter>, VecDeque<Waiter>) =
+        queue.drain(..).partition(|w| w.address == address);
+    *queue = others;
+    let result = UnparkResult {
+        unparked_threads: unparked.len(),
+        have_more_threads: false,
+    };
+    callback(result);
+    drop(queue);
+
+    for waiter in unparked {
+        wake(waiter);
+    }
+    result
+}
+
+fn wake(waiter: Waiter) {
+    waiter.parker.unparked.store(true, Ordering::Release);
+    waiter.parker.thread.unpark();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{park, unpark_all, unpark_one, ParkResult, UnparkResult};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
+
+    /// Wait until `n` threads are parked on `address`, by looking at the queue.
+    fn wait_for_parked(address: usize, n: usize) {
+        while super::bucket(address)
+            .lock()
+            .iter()
+            .filter(|w| w.address == address)
+            .count()
+            < n
+        {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        let x = 0u8;
+        let address = &x as *const u8 as usize;
+        assert_eq!(park(address, || false, None), ParkResult::Invalid);
+        assert_eq!(unpark_one(address, |_| {}), UnparkResult::default());
+    }
+
+    #[test]
+    fn timed_out() {
+        let x = 0u8;
+        let address = &x as *const u8 as usize;
+        let start = Instant::now();
+        assert_eq!(
+            park(address, || true, Some(Duration::from_millis(20))),
+            ParkResult::TimedOut
+        );
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        // We're no longer in the queue.
+        assert_eq!(unpark_all(address, |_| {}).unparked_threads, 0);
+    }
+
+    #[test]
+    fn unpark_one_is_fifo() {
+        let x = 0u8;
+        let address = &x as *const u8 as usize;
+        let order = Mutex::new(Vec::new());
+        thread::scope(|s| {
+            for i in 0..4 {
+                let order = &order;
+                s.spawn(move || {
+                    assert_eq!(park(address, || true, None), ParkResult::Unparked);
+                    order.lock().unwrap().push(i);
+                });
+                // Park them one by one, so we know the order.
+                wait_for_parked(address, i + 1);
+            }
+            for i in 0..4 {
+                let result = unpark_one(address, |result| {
+                    assert_eq!(result.unparked_threads, 1);
+                    assert_eq!(result.have_more_threads, i < 3);
+                });
+                assert_eq!(result.unparked_threads, 1);
+                // Wait for it to record itself before unparking the next one.
+                while order.lock().unwrap().len() <= i {
+                    thread::sleep(Duration::from_millis(1));
+                }
+            }
+        });
+        assert_eq!(*order.lock().unwrap(), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn unpark_all_unparks_only_its_address() {
+        let xs = [0u8; 2];
+        let a = &xs[0] as *const u8 as usize;
+        let b = &xs[1] as *const u8 as usize;
+        let unparked = AtomicUsize::new(0);
+        thread::scope(|s| {
+            for address in [a, a, a, b] {
+                let unparked = &unparked;
+                s.spawn(move || {
+                    park(address, || true, None);
+                    unparked.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+            wait_for_parked(a, 3);
+            wait_for_parked(b, 1);
+            assert_eq!(unpark_all(a, |_| {}).unparked_threads, 3);
+            while unparked.load(Ordering::Relaxed) < 3 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            thread::sleep(Duration::from_millis(10));
+            assert_eq!(unparked.load(Ordering::Relaxed), 3);
+            assert_eq!(unpark_one(b, |_| {}).unparked_threads, 1);
+        });
+    }
+}
@@ -0,0 +1,220 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::atomic::{fence, AtomicU32, Ordering},
+};
+
+use ch08::futex::{
+    gettid, lock_pi_private, trylock_pi_private, unlock_pi_private, Errno, FUTEX_TID_MASK,
+};
+
+/// A mutex with priority inheritance: while a thread is blocked on the lock,
+/// the kernel runs the owner with (at least) the priority of that thread.
+/// Without that, a low priority owner can be kept from running by medium priority
+/// threads, and with it the high priority thread waiting for the lock
+/// (priority inversion).
+///
+/// For that, the kernel has to know who owns the lock, so the state is not
+/// a small number like in `mutex_v3`, but the thread id of the owner.
+pub struct PiMutex<T> {
+    /// 0: unlocked,
+    /// tid: locked by the thread with this id, no other threads waiting
+    /// tid | FUTEX_WAITERS: locked, other threads waiting (set by the kernel)
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for PiMutex<T> where T: Send {}
+
+pub struct PiMutexGuard<'a, T> {
+    mutex: &'a PiMutex<T>,
+    // The kernel only accepts an unlock from the owner, so the guard must be
+    // dropped on the thread that created it: make it !Send with a raw pointer type
+    _no_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for PiMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for PiMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+/// The thread id of the current thread, cached to avoid a syscall per lock.
+fn current_tid() -> u32 {
+    thread_local!(static TID: u32 = gettid());
+    TID.with(|tid| *tid)
+}
+
+impl<T> PiMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked at creation
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn lock(&self) -> PiMutexGuard<'_, T> {
+        if self
+            .state
+            .compare_exchange(0, current_tid(), Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            lock_contended(&self.state);
+        }
+        PiMutexGuard {
+            mutex: self,
+            _no_send: PhantomData,
+        }
+    }
+
+    /// Lock the mutex if nobody holds it, without blocking.
+    pub fn try_lock(&self) -> Option<PiMutexGuard<'_, T>> {
+        let locked = match self.state.compare_exchange(
+            0,
+            current_tid(),
+            Ordering::Acquire,
+            Ordering::Relaxed,
+        ) {
+            Ok(_) => true,
+            // Nobody owns it, but the kernel still has to clean up (e.g. the waiters
+            // bit is still set): let the kernel take it for us.
+            Err(s) if s & FUTEX_TID_MASK == 0 => {
+                let locked = trylock_pi_private(&self.state).is_ok();
+                fence(Ordering::Acquire);
+                locked
+            }
+            Err(_) => false,
+        };
+        locked.then(|| PiMutexGuard {
+            mutex: self,
+            _no_send: PhantomData,
+        })
+    }
+}
+
+fn lock_contended(state: &AtomicU32) {
+    loop {
+        // The kernel sets the waiters bit, sleeps until the lock is handed
+        // over to us, and stores our tid in the state.
+        match lock_pi_private(state) {
+            Ok(()) => break,
+            // The owner is exiting, or we got interrupted: try again.
+            Err(Errno(libc::EAGAIN | libc::EINTR)) => continue,
+            Err(Errno(libc::EDEADLK)) => panic!("PiMutex locked twice by the same thread"),
+            Err(e) => panic!("FUTEX_LOCK_PI failed: {e}"),
+        }
+    }
+    // The kernel's atomic operations are full barriers, but the compiler
+    // doesn't know that.
+    fence(Ordering::Acquire);
+}
+
+impl<T> Drop for PiMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let state = &self.mutex.state;
+        // Fast path: still just our tid, so nobody is waiting.
+        if state
+            .compare_exchange(current_tid(), 0, Ordering::Release, Ordering::Relaxed)
+            .is_err()
+        {
+            // The waiters bit is set: let the kernel hand the lock over
+            // to the highest priority waiter.
+            fence(Ordering::Release);
+            unlock_pi_private(state).expect("FUTEX_UNLOCK_PI failed");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PiMutex;
+    use ch08::futex::{gettid, FUTEX_TID_MASK, FUTEX_WAITERS};
+    use std::{
+        sync::atomic::Ordering,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn state_is_owner_tid() {
+        let m = PiMutex::new(0);
+        let g = m.lock();
+        assert_eq!(m.state.load(Ordering::Relaxed), gettid());
+        drop(g);
+        assert_eq!(m.state.load(Ordering::Relaxed), 0);
+
+        thread::scope(|s| {
+            s.spawn(|| {
+                let _g = m.lock();
+                assert_eq!(m.state.load(Ordering::Relaxed), gettid());
+            });
+        });
+        assert_eq!(m.state.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn waiters_bit() {
+        let m = PiMutex::new(0);
+        let main_tid = gettid();
+        let g = m.lock();
+        thread::scope(|s| {
+            s.spawn(|| {
+                let mut g = m.lock();
+                // The lock was handed over to us directly.
+                assert_eq!(m.state.load(Ordering::Relaxed) & FUTEX_TID_MASK, gettid());
+                *g += 1;
+            });
+
+            // Wait until the kernel marked the lock as contended.
+            let start = Instant::now();
+            while m.state.load(Ordering::Relaxed) & FUTEX_WAITERS == 0 {
+                assert!(start.elapsed() < Duration::from_secs(10));
+                thread::sleep(Duration::from_millis(1));
+            }
+            // It's still ours.
+            assert_eq!(m.state.load(Ordering::Relaxed) & FUTEX_TID_MASK, main_tid);
+            drop(g);
+        });
+        assert_eq!(m.state.load(Ordering::Relaxed), 0);
+        assert_eq!(*m.lock(), 1);
+    }
+
+    #[test]
+    fn try_lock() {
+        let m = PiMutex::new(0);
+        let g = m.lock();
+        thread::scope(|s| {
+            s.spawn(|| assert!(m.try_lock().is_none()));
+        });
+        drop(g);
+        thread::scope(|s| {
+            s.spawn(|| assert!(m.try_lock().is_some()));
+        });
+        assert_eq!(m.state.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn mutual_exclusion() {
+        let m = PiMutex::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        *m.lock() += 1;
+                    }
+                });
+            }
+        });
+        assert_eq!(*m.lock(), 40_000);
+        assert_eq!(m.state.load(Ordering::Relaxed) & FUTEX_WAITERS, 0);
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    error::Error,
+    fmt,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+};
+
+use crate::mutex_v3;
+
+/// A `mutex_v3::Mutex` that gets poisoned when a thread panics while holding the lock,
+/// like `std::sync::Mutex`.
+pub struct Mutex<T> {
+    inner: mutex_v3::Mutex<T>,
+    poisoned: AtomicBool,
+}
+
+pub struct MutexGuard<'a, T> {
+    guard: mutex_v3::MutexGuard<'a, T>,
+    poisoned: &'a AtomicBool,
+    /// Whether the thread was already panicking when it locked the mutex.
+    /// Such a thread did not leave the data half-updated by panicking
+    /// inside the critical section, so it should not poison the mutex.
+    panicking: bool,
+}
+
+/// The error returned when locking a poisoned mutex.
+/// The lock is still acquired, and the guard can be recovered with `into_inner`.
+pub struct PoisonError<G> {
+    guard: G,
+}
+
+pub type LockResult<G> = Result<G, PoisonError<G>>;
+
+impl<T> Mutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            inner: mutex_v3::Mutex::new(value),
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+        let guard = MutexGuard {
+            guard: self.inner.lock(),
+            poisoned: &self.poisoned,
+            panicking: thread::panicking(),
+        };
+
+        // Relaxed is enough: the flag is only modified while holding the lock,
+        // and locking already synchronizes with the previous unlock.
+        if self.poisoned.load(Ordering::Relaxed) {
+            Err(PoisonError { guard })
+        } else {
+            Ok(guard)
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Relaxed)
+    }
+
+    /// Mark the mutex as no longer poisoned, e.g. after the caller has restored
+    /// the data to a consistent state through the guard of a `PoisonError`.
+    pub fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Relaxed);
+    }
+}
+
+impl<T> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Record the panic before the inner guard (dropped right after this
+        // function) unlocks the mutex, so the next owner sees the flag.
+        if !self.panicking && thread::panicking() {
+            self.poisoned.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for MutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+impl<G> PoisonError<G> {
+    // Only used by SharedMutex, which is Linux-only.
+    #[cfg(target_os = "linux")]
+    pub(crate) fn new(guard: G) -> Self {
+        Self { guard }
+    }
+
+    pub fn into_inner(self) -> G {
+        self.guard
+    }
+
+    pub fn get_ref(&self) -> &G {
+        &self.guard
+    }
+
+    pub fn get_mut(&mut self) -> &mut G {
+        &mut self.guard
+    }
+}
+
+impl<G> fmt::Debug for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoisonError").finish_non_exhaustive()
+    }
+}
+
+impl<G> fmt::Display for PoisonError<G> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("poisoned lock: another thread panicked while holding it")
+    }
+}
+
+impl<G> Error for PoisonError<G> {}
+
+#[cfg(test)]
+mod tests {
+    use super::Mutex;
+    use std::thread;
+
+    #[test]
+    fn not_poisoned_without_panic() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            s.spawn(|| *m.lock().unwrap() += 1);
+        });
+        assert!(!m.is_poisoned());
+        assert_eq!(*m.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn panic_while_locked_poisons() {
+        let m = Mutex::new(vec![1, 2, 3]);
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| {
+                    let mut v = m.lock().unwrap();
+                    v.push(4);
+                    panic!("half-way through the update");
+                })
+                .join();
+            assert!(result.is_err());
+        });
+
+        assert!(m.is_poisoned());
+
+        // The data is still reachable through the error.
+        let err = m.lock().unwrap_err();
+        assert_eq!(*err.into_inner(), [1, 2, 3, 4]);
+
+        // Still poisoned: recovering the guard doesn't clear the flag.
+        assert!(m.lock().is_err());
+    }
+
+    #[test]
+    fn clear_poison() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| {
+                    let _g = m.lock().unwrap();
+                    panic!();
+                })
+                .join();
+            assert!(result.is_err());
+        });
+
+        let mut g = m.lock().unwrap_err().into_inner();
+        *g = 42; // restore a consistent state
+        drop(g);
+        m.clear_poison();
+
+        assert!(!m.is_poisoned());
+        assert_eq!(*m.lock().unwrap(), 42);
+    }
+
+    #[test]
+    fn panic_without_lock_does_not_poison() {
+        let m = Mutex::new(0);
+        thread::scope(|s| {
+            let result = s
+                .spawn(|| {
+                    drop(m.lock().unwrap());
+                    panic!();
+                })
+                .join();
+            assert!(result.is_err());
+        });
+        assert!(!m.is_poisoned());
+    }
+}
@@ -0,0 +1,188 @@
+use std::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ops::Deref,
+    sync::atomic::{AtomicU32, AtomicUsize, Ordering},
+};
+
+use crate::{mutex_v3, spin::FixedSpin};
+
+/// A mutex that can be locked again by the thread that already holds it.
+///
+/// Since the same thread can hold several guards at the same time, the guards
+/// only give out `&T`. Use a `Cell` or `RefCell` inside for mutation.
+pub struct ReentrantMutex<T> {
+    /// Same state machine as `mutex_v3::Mutex`:
+    /// 0: unlocked,
+    /// 1: locked, no other threads waiting
+    /// 2: locked, other threads waiting
+    state: AtomicU32,
+    /// The id of the thread holding the lock, or 0 if unlocked
+    owner: AtomicUsize,
+    /// How many guards the owner holds. Only accessed by the owner.
+    lock_count: UnsafeCell<usize>,
+    value: T,
+}
+
+unsafe impl<T> Sync for ReentrantMutex<T> where T: Send {}
+
+pub struct ReentrantMutexGuard<'a, T> {
+    mutex: &'a ReentrantMutex<T>,
+    // The lock belongs to a thread, so the guard must be dropped on the thread
+    // that created it: make it !Send with a raw pointer type, which does not implement Send
+    _no_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for ReentrantMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.mutex.value
+    }
+}
+
+/// A non-zero number unique to each running thread: the address of a thread local.
+/// Addresses can be reused once a thread exits, but by then it can't hold a lock anymore
+/// (unless a guard was leaked).
+fn current_thread_id() -> usize {
+    thread_local!(static ID: u8 = const { 0 });
+    ID.with(|id| id as *const u8 as usize)
+}
+
+impl<T> ReentrantMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked at creation
+            owner: AtomicUsize::new(0),
+            lock_count: UnsafeCell::new(0),
+            value,
+        }
+    }
+
+    pub fn lock(&self) -> ReentrantMutexGuard<'_, T> {
+        let id = current_thread_id();
+
+        // Relaxed is enough: only this thread ever stores its own id, so we can
+        // only load our id if we stored it ourselves, while holding the lock.
+        if self.owner.load(Ordering::Relaxed) == id {
+            // Safety: we own the lock, so nobody else accesses the count
+            let count = unsafe { &mut *self.lock_count.get() };
+            *count = count.checked_add(1).expect("lock count overflow");
+        } else {
+            mutex_v3::lock(&self.state, &FixedSpin::<100>);
+            self.owner.store(id, Ordering::Relaxed);
+            // Safety: we just locked the lock
+            unsafe { *self.lock_count.get() = 1 };
+        }
+
+        ReentrantMutexGuard {
+            mutex: self,
+            _no_send: PhantomData,
+        }
+    }
+}
+
+impl<T> Drop for ReentrantMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        // Safety: the guard exists, so the current thread owns the lock
+        let count = unsafe { &mut *self.mutex.lock_count.get() };
+        *count -= 1;
+        if *count == 0 {
+            // Last guard: give up ownership before unlocking
+            self.mutex.owner.store(0, Ordering::Relaxed);
+            mutex_v3::unlock(&self.mutex.state);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReentrantMutex;
+    use std::{
+        cell::Cell,
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn nested_locking() {
+        let m = ReentrantMutex::new(Cell::new(0));
+
+        let g1 = m.lock();
+        g1.set(1);
+        {
+            let g2 = m.lock();
+            g2.set(g2.get() + 1);
+            let g3 = m.lock();
+            g3.set(g3.get() + 1);
+        }
+        assert_eq!(g1.get(), 3);
+        drop(g1);
+
+        // Fully unlocked again
+        assert_eq!(m.state.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn reentrant_callback() {
+        fn visit(m: &ReentrantMutex<Cell<u32>>, depth: u32) {
+            let g = m.lock();
+            g.set(g.get() + 1);
+            if depth > 0 {
+                visit(m, depth - 1);
+            }
+        }
+
+        let m = ReentrantMutex::new(Cell::new(0));
+        visit(&m, 10);
+        assert_eq!(m.lock().get(), 11);
+    }
+
+    #[test]
+    fn cross_thread_exclusion() {
+        let m = ReentrantMutex::new(Cell::new(0));
+        let locked_by_other = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let g = m.lock();
+            let _g2 = m.lock();
+
+            s.spawn(|| {
+                let g = m.lock();
+                locked_by_other.store(true, Ordering::Relaxed);
+                g.set(g.get() + 1);
+            });
+
+            thread::sleep(Duration::from_millis(100));
+            assert!(!locked_by_other.load(Ordering::Relaxed));
+            drop(g);
+
+            // Still locked through _g2
+            thread::sleep(Duration::from_millis(100));
+            assert!(!locked_by_other.load(Ordering::Relaxed));
+        });
+
+        assert!(locked_by_other.load(Ordering::Relaxed));
+        assert_eq!(m.lock().get(), 1);
+    }
+
+    #[test]
+    fn stress() {
+        let m = ReentrantMutex::new(Cell::new(0));
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    for _ in 0..10_000 {
+                        let g1 = m.lock();
+                        let g2 = m.lock();
+                        // Nobody else can change the value between these two lines
+                        let v = g1.get();
+                        g2.set(v + 1);
+                    }
+                });
+            }
+        });
+        assert_eq!(m.lock().get(), 40_000);
+    }
+}
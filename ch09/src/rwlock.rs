@@ -0,0 +1,53 @@
+// Reader-writer locks, in the same successive versions as the mutexes:
+//
+// - `v1`: a basic one, in which waiting writers busy-loop on every reader unlock.
+// - `v2`: writers sleep on a separate counter that is only bumped when they may
+//   be able to take the lock.
+// - `v3`: writer-preferring, so that a steady stream of readers cannot starve writers.
+
+// Tests shared by all versions.
+#[cfg(test)]
+macro_rules! common_tests {
+    ($RwLock:ident) => {
+        #[test]
+        fn multiple_readers_at_the_same_time() {
+            let lock = $RwLock::new(5);
+            let r1 = lock.read();
+            let r2 = lock.read();
+            assert_eq!(*r1 + *r2, 10);
+        }
+
+        #[test]
+        fn stress() {
+            // Writers always update both values together, so readers must never
+            // observe them being different.
+            let lock = $RwLock::new((0u64, 0u64));
+
+            std::thread::scope(|s| {
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..10_000 {
+                            let mut w = lock.write();
+                            w.0 += 1;
+                            w.1 += 1;
+                        }
+                    });
+                }
+                for _ in 0..4 {
+                    s.spawn(|| {
+                        for _ in 0..10_000 {
+                            let r = lock.read();
+                            assert_eq!(r.0, r.1);
+                        }
+                    });
+                }
+            });
+
+            assert_eq!(*lock.read(), (40_000, 40_000));
+        }
+    };
+}
+
+pub mod v1;
+pub mod v2;
+pub mod v3;
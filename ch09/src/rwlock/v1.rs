@@ -0,0 +1,122 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::futex::{wait, wake_all, wake_one};
+
+pub struct RwLock<T> {
+    /// The number of readers, or u32::MAX if write-locked.
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// Readers share &T across threads, so T must also be Sync.
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked at creation
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s < u32::MAX {
+                // Not write-locked: try to add one reader
+                assert!(s != u32::MAX - 1, "too many readers");
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            }
+            if s == u32::MAX {
+                // Write-locked: sleep until the writer unlocks
+                wait(&self.state, u32::MAX);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        // Only an unlocked lock (no readers, no writer) can be write-locked.
+        while let Err(s) =
+            self.state
+                .compare_exchange(0, u32::MAX, Ordering::Acquire, Ordering::Relaxed)
+        {
+            // Wait while already locked.
+            // Note that every reader unlock changes the state, so a waiting writer
+            // is woken up (and goes back to sleep) many times while readers come
+            // and go. See v2.
+            wait(&self.state, s);
+        }
+        WriteGuard { rwlock: self }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.rwlock.state.fetch_sub(1, Ordering::Release) == 1 {
+            // We were the last reader: the lock is now unlocked.
+            // Only writers can be waiting at this point (readers don't wait
+            // on a read-locked lock), and one writer is enough.
+            wake_one(&self.rwlock.state);
+        }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Ordering::Release);
+
+        // Wake all waiting readers and writers: all readers can
+        // lock the lock at the same time.
+        wake_all(&self.rwlock.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RwLock;
+
+    common_tests!(RwLock);
+}
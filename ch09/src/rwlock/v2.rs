@@ -0,0 +1,138 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::futex::{wait, wake_all, wake_one};
+
+pub struct RwLock<T> {
+    /// The number of readers, or u32::MAX if write-locked.
+    state: AtomicU32,
+    /// Incremented to wake up writers.
+    /// Writers wait on this instead of `state`, so that they are not woken up
+    /// by every reader that unlocks, only by the one that leaves the lock unlocked.
+    writer_wake_counter: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// Readers share &T across threads, so T must also be Sync.
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked at creation
+            writer_wake_counter: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s < u32::MAX {
+                // Not write-locked: try to add one reader
+                assert!(s != u32::MAX - 1, "too many readers");
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            }
+            if s == u32::MAX {
+                // Write-locked: sleep until the writer unlocks
+                wait(&self.state, u32::MAX);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        while self
+            .state
+            .compare_exchange(0, u32::MAX, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            // Load the counter *before* checking the state again.
+            // The Acquire load synchronizes with the Release increment in the unlock
+            // functions, so if the lock gets unlocked after the check below,
+            // the counter will have changed and `wait` returns immediately.
+            let w = self.writer_wake_counter.load(Ordering::Acquire);
+            if self.state.load(Ordering::Relaxed) != 0 {
+                // Still locked: wait for a wake up on the counter.
+                wait(&self.writer_wake_counter, w);
+            }
+        }
+        WriteGuard { rwlock: self }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.rwlock.state.fetch_sub(1, Ordering::Release) == 1 {
+            // We were the last reader: the lock is now unlocked.
+            // Wake one waiting writer, if any.
+            self.rwlock
+                .writer_wake_counter
+                .fetch_add(1, Ordering::Release);
+            wake_one(&self.rwlock.writer_wake_counter);
+        }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Ordering::Release);
+
+        // Wake one writer and all readers.
+        // We don't know which ones are waiting, and the ones that lose the race
+        // will simply go back to sleep.
+        self.rwlock
+            .writer_wake_counter
+            .fetch_add(1, Ordering::Release);
+        wake_one(&self.rwlock.writer_wake_counter);
+        wake_all(&self.rwlock.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RwLock;
+
+    common_tests!(RwLock);
+}
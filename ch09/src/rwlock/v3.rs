@@ -0,0 +1,204 @@
+use std::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use crate::futex::{wait, wake_all, wake_one};
+
+pub struct RwLock<T> {
+    /// The number of read locks times two, plus one if there's a writer waiting.
+    /// u32::MAX if write-locked.
+    ///
+    /// This means that readers may acquire the lock when the state is even,
+    /// but need to block when odd. That way, a waiting writer blocks new
+    /// readers and is not starved by a continuous stream of them.
+    state: AtomicU32,
+    /// Incremented to wake up writers.
+    writer_wake_counter: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+// Readers share &T across threads, so T must also be Sync.
+unsafe impl<T> Sync for RwLock<T> where T: Send + Sync {}
+
+pub struct ReadGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+pub struct WriteGuard<'a, T> {
+    rwlock: &'a RwLock<T>,
+}
+
+impl<T> Deref for ReadGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> Deref for WriteGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.rwlock.value.get() }
+    }
+}
+
+impl<T> DerefMut for WriteGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.rwlock.value.get() }
+    }
+}
+
+impl<T> RwLock<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked at creation
+            writer_wake_counter: AtomicU32::new(0),
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    pub fn read(&self) -> ReadGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            if s.is_multiple_of(2) {
+                // Even: no writer waiting, and not write-locked (u32::MAX is odd)
+                assert!(s != u32::MAX - 2, "too many readers");
+                match self.state.compare_exchange_weak(
+                    s,
+                    s + 2,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return ReadGuard { rwlock: self },
+                    Err(e) => s = e,
+                }
+            }
+            if !s.is_multiple_of(2) {
+                // Odd: write-locked, or a writer is waiting. Let the writer go first.
+                wait(&self.state, s);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn write(&self) -> WriteGuard<'_, T> {
+        let mut s = self.state.load(Ordering::Relaxed);
+        loop {
+            // Try to lock if unlocked (0), or unlocked with only writers waiting (1).
+            if s <= 1 {
+                match self
+                    .state
+                    .compare_exchange(s, u32::MAX, Ordering::Acquire, Ordering::Relaxed)
+                {
+                    Ok(_) => return WriteGuard { rwlock: self },
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+
+            // Block new readers, by making sure the state is odd.
+            if s.is_multiple_of(2) {
+                match self
+                    .state
+                    .compare_exchange(s, s + 1, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => {}
+                    Err(e) => {
+                        s = e;
+                        continue;
+                    }
+                }
+            }
+
+            // Wait, if it's still locked
+            let w = self.writer_wake_counter.load(Ordering::Acquire);
+            s = self.state.load(Ordering::Relaxed);
+            if s >= 2 {
+                wait(&self.writer_wake_counter, w);
+                s = self.state.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Drop for ReadGuard<'_, T> {
+    fn drop(&mut self) {
+        // Decrement the state by 2 to remove one read-lock.
+        if self.rwlock.state.fetch_sub(2, Ordering::Release) == 3 {
+            // If we decremented from 3 to 1, that means
+            // the lock is now unlocked _and_ there is a waiting writer,
+            // which we wake up.
+            self.rwlock
+                .writer_wake_counter
+                .fetch_add(1, Ordering::Release);
+            wake_one(&self.rwlock.writer_wake_counter);
+        }
+    }
+}
+
+impl<T> Drop for WriteGuard<'_, T> {
+    fn drop(&mut self) {
+        self.rwlock.state.store(0, Ordering::Release);
+
+        // Wake one writer and all readers.
+        // A woken writer will set the state back to odd, blocking the readers again.
+        self.rwlock
+            .writer_wake_counter
+            .fetch_add(1, Ordering::Release);
+        wake_one(&self.rwlock.writer_wake_counter);
+        wake_all(&self.rwlock.state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RwLock;
+    use std::{
+        sync::atomic::{AtomicBool, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    common_tests!(RwLock);
+
+    #[test]
+    fn waiting_writer_blocks_new_readers() {
+        let lock = RwLock::new(0);
+        let reader_done = AtomicBool::new(false);
+
+        thread::scope(|s| {
+            let r = lock.read();
+
+            s.spawn(|| {
+                *lock.write() = 1;
+            });
+
+            // Wait until the writer announced itself by making the state odd.
+            while lock.state.load(Ordering::Relaxed).is_multiple_of(2) {
+                thread::yield_now();
+            }
+
+            let new_reader = s.spawn(|| {
+                let value = *lock.read();
+                reader_done.store(true, Ordering::Relaxed);
+                value
+            });
+
+            // The new reader must not get in while the writer is waiting,
+            // even though the lock is only read-locked.
+            thread::sleep(Duration::from_millis(100));
+            assert!(!reader_done.load(Ordering::Relaxed));
+
+            drop(r);
+
+            // The writer goes first, so the new reader sees its write.
+            assert_eq!(new_reader.join().unwrap(), 1);
+        });
+    }
+}
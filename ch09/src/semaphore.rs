@@ -0,0 +1,205 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::futex::{wait, wake_all};
+
+pub struct Semaphore {
+    /// The number of permits that are available
+    permits: AtomicU32,
+    /// The number of threads sleeping in `acquire`, used to skip the wake
+    /// syscall when nobody is waiting.
+    waiters: AtomicU32,
+}
+
+/// Holds a number of permits of a `Semaphore`, and gives them back when dropped.
+pub struct SemaphorePermit<'a> {
+    semaphore: &'a Semaphore,
+    permits: u32,
+}
+
+impl Semaphore {
+    pub const fn new(permits: u32) -> Self {
+        Self {
+            permits: AtomicU32::new(permits),
+            waiters: AtomicU32::new(0),
+        }
+    }
+
+    pub fn available_permits(&self) -> u32 {
+        self.permits.load(Ordering::Relaxed)
+    }
+
+    /// Take `n` permits, blocking until enough of them are available.
+    pub fn acquire(&self, n: u32) -> SemaphorePermit<'_> {
+        loop {
+            if let Some(permit) = self.try_acquire(n) {
+                return permit;
+            }
+
+            // SeqCst on waiters and permits, both here and in release():
+            // either release() sees that we're waiting, or we see its new permits.
+            // With Acquire/Release only, both could miss each other's update.
+            self.waiters.fetch_add(1, Ordering::SeqCst);
+            let p = self.permits.load(Ordering::SeqCst);
+            if p < n {
+                // Sleep until the number of permits changes.
+                // A release of fewer permits than we need wakes us up for nothing,
+                // but we'll simply go back to sleep.
+                wait(&self.permits, p);
+            }
+            self.waiters.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Take `n` permits if they are available right now, without blocking.
+    pub fn try_acquire(&self, n: u32) -> Option<SemaphorePermit<'_>> {
+        let mut p = self.permits.load(Ordering::Relaxed);
+        while p >= n {
+            match self
+                .permits
+                .compare_exchange_weak(p, p - n, Ordering::Acquire, Ordering::Relaxed)
+            {
+                Ok(_) => {
+                    return Some(SemaphorePermit {
+                        semaphore: self,
+                        permits: n,
+                    })
+                }
+                Err(e) => p = e,
+            }
+        }
+        None
+    }
+
+    /// Add `n` permits, e.g. ones that were taken out with `SemaphorePermit::forget`.
+    ///
+    /// Panics if that would overflow the number of permits, leaving it unchanged.
+    pub fn release(&self, n: u32) {
+        if !self.add_permits(n) {
+            panic!("too many permits");
+        }
+    }
+
+    /// Returns false, without changing anything, if there would be too many permits.
+    fn add_permits(&self, n: u32) -> bool {
+        // Check before storing, so other threads never see a wrapped-around count.
+        if self
+            .permits
+            .fetch_update(Ordering::SeqCst, Ordering::Relaxed, |p| p.checked_add(n))
+            .is_err()
+        {
+            return false;
+        }
+
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            // Waiters may need different numbers of permits, so we don't know
+            // which ones can continue. Wake them all.
+            wake_all(&self.permits);
+        }
+        true
+    }
+}
+
+impl SemaphorePermit<'_> {
+    pub fn permits(&self) -> u32 {
+        self.permits
+    }
+
+    /// Drop the permit without giving the permits back to the semaphore.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+impl Drop for SemaphorePermit<'_> {
+    fn drop(&mut self) {
+        // Panicking while already unwinding would abort the process.
+        if !self.semaphore.add_permits(self.permits) && !std::thread::panicking() {
+            panic!("too many permits");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Semaphore;
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    #[test]
+    fn try_acquire() {
+        let s = Semaphore::new(3);
+        let p1 = s.try_acquire(2).unwrap();
+        assert!(s.try_acquire(2).is_none());
+        let p2 = s.try_acquire(1).unwrap();
+        assert_eq!(s.available_permits(), 0);
+        drop(p1);
+        assert_eq!(s.available_permits(), 2);
+        drop(p2);
+        assert_eq!(s.available_permits(), 3);
+    }
+
+    #[test]
+    fn forget_and_release() {
+        let s = Semaphore::new(1);
+        s.acquire(1).forget();
+        assert!(s.try_acquire(1).is_none());
+        s.release(1);
+        assert!(s.try_acquire(1).is_some());
+    }
+
+    #[test]
+    fn release_overflow() {
+        let s = Semaphore::new(u32::MAX - 1);
+        let result = std::panic::catch_unwind(|| s.release(2));
+        assert!(result.is_err());
+        // Not wrapped around.
+        assert_eq!(s.available_permits(), u32::MAX - 1);
+        s.release(1);
+        assert_eq!(s.available_permits(), u32::MAX);
+    }
+
+    #[test]
+    fn acquire_blocks_until_released() {
+        let s = Semaphore::new(2);
+        thread::scope(|scope| {
+            let p = s.acquire(2);
+            let t = scope.spawn(|| s.acquire(1).permits());
+            thread::sleep(Duration::from_millis(100));
+            assert!(!t.is_finished());
+            drop(p);
+            assert_eq!(t.join().unwrap(), 1);
+        });
+    }
+
+    #[test]
+    fn permits_never_exceeded() {
+        const PERMITS: u32 = 3;
+        let s = Semaphore::new(PERMITS);
+        let in_use = AtomicU32::new(0);
+        let max_in_use = AtomicU32::new(0);
+
+        thread::scope(|scope| {
+            for i in 0..16 {
+                let (s, in_use, max_in_use) = (&s, &in_use, &max_in_use);
+                scope.spawn(move || {
+                    // Mix single and multiple permit acquisitions
+                    let n = i % 2 + 1;
+                    for _ in 0..2_000 {
+                        let _permit = s.acquire(n);
+                        let now = in_use.fetch_add(n, Ordering::Relaxed) + n;
+                        assert!(now <= PERMITS);
+                        max_in_use.fetch_max(now, Ordering::Relaxed);
+                        std::hint::spin_loop();
+                        in_use.fetch_sub(n, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        assert!(max_in_use.load(Ordering::Relaxed) <= PERMITS);
+        assert_eq!(s.available_permits(), PERMITS);
+    }
+}
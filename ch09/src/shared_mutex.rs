@@ -0,0 +1,359 @@
+use std::{
+    cell::UnsafeCell,
+    fmt,
+    io::{self, Write},
+    mem::size_of,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use ch08::futex::{futex_wait, gettid, wake, WaitOutcome};
+
+use crate::poison::{LockResult, PoisonError};
+
+/// Set in the state when other threads or processes may be waiting.
+const WAITERS: u32 = 0x8000_0000;
+
+/// How often a waiter checks whether the owner is still alive.
+const OWNER_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A mutex that can be shared between processes, by placing it in shared memory
+/// (e.g. with `SharedMemory`). It only uses shared (non-private) futex operations,
+/// which the kernel keys on the physical page rather than the address, so they
+/// work even if the memory is mapped at different addresses in different processes.
+///
+/// The state is the thread id of the owner, so if the owner thread (or its whole
+/// process) dies while holding the lock, the next thread to lock it notices and
+/// takes over the lock, getting a `PoisonError`, since the data may have been
+/// left half-updated.
+///
+/// Waiters only check on the owner every `OWNER_CHECK_INTERVAL`, so taking over
+/// from a dead owner can take that long. And thread ids are reused: if a new
+/// thread gets the id of the dead owner in the meantime, the lock looks held
+/// until that thread exits too.
+///
+/// `T` must not contain pointers or references, since those are meaningless in
+/// the other processes.
+#[repr(C)]
+pub struct SharedMutex<T> {
+    /// 0: unlocked,
+    /// tid: locked by the thread with this id, nobody waiting
+    /// tid | WAITERS: locked, others (maybe) waiting
+    state: AtomicU32,
+    value: UnsafeCell<T>,
+}
+
+unsafe impl<T> Sync for SharedMutex<T> where T: Send {}
+
+pub struct SharedMutexGuard<'a, T> {
+    mutex: &'a SharedMutex<T>,
+}
+
+impl<T> Deref for SharedMutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.value.get() }
+    }
+}
+
+impl<T> DerefMut for SharedMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.value.get() }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for SharedMutexGuard<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+fn current_tid() -> u32 {
+    // Not cached (unlike in `pi_mutex`), since the thread in a forked child has a new id.
+    gettid()
+}
+
+/// Whether the thread with this tid is still running.
+/// (Tids are reused, so a new thread could have taken the place of a dead owner.
+/// We'd then wait for that thread to exit, and only then take over the lock.)
+fn is_alive(tid: u32) -> bool {
+    // kill(tid, 0) would also succeed for a zombie: a process that exited, but was not
+    // reaped by its parent yet. So look at the thread state in /proc/<tid>/stat
+    // instead, which exists for every thread, not just the main one. Without
+    // allocating, since we might be a child forked from a multi-threaded process.
+    let mut path = [0u8; 32];
+    write!(&mut path[..], "/proc/{tid}/stat\0").unwrap();
+    let fd = unsafe { libc::open(path.as_ptr() as *const libc::c_char, libc::O_RDONLY) };
+    if fd < 0 {
+        return io::Error::last_os_error().raw_os_error() != Some(libc::ENOENT);
+    }
+    let mut stat = [0u8; 512];
+    let n = unsafe { libc::read(fd, stat.as_mut_ptr() as *mut libc::c_void, stat.len()) };
+    unsafe { libc::close(fd) };
+    let stat = &stat[..n.max(0) as usize];
+
+    // "tid (command) state ...", where the command itself may contain parentheses.
+    match stat.iter().rposition(|&c| c == b')') {
+        Some(i) => !matches!(stat.get(i + 2), Some(b'Z' | b'X')),
+        None => true,
+    }
+}
+
+impl<T> SharedMutex<T> {
+    pub const fn new(value: T) -> Self {
+        Self {
+            state: AtomicU32::new(0), // unlocked at creation
+            value: UnsafeCell::new(value),
+        }
+    }
+
+    /// Returns an error (with the lock acquired) if the previous owner died
+    /// while holding the lock. This is only reported to the one that takes over.
+    pub fn lock(&self) -> LockResult<SharedMutexGuard<'_, T>> {
+        let tid = current_tid();
+        let owner_died = self
+            .state
+            .compare_exchange(0, tid, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+            && lock_contended(&self.state, tid);
+
+        let guard = SharedMutexGuard { mutex: self };
+        if owner_died {
+            Err(PoisonError::new(guard))
+        } else {
+            Ok(guard)
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<SharedMutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(0, current_tid(), Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| SharedMutexGuard { mutex: self })
+    }
+}
+
+/// Returns whether we took the lock over from a dead owner.
+fn lock_contended(state: &AtomicU32, tid: u32) -> bool {
+    loop {
+        let s = state.load(Ordering::Relaxed);
+
+        if s == 0 {
+            // Like the swap(2) in `mutex_v3`: we don't know whether others
+            // are still waiting, so assume they are.
+            if state
+                .compare_exchange(0, tid | WAITERS, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return false;
+            }
+            continue;
+        }
+
+        if s & WAITERS == 0
+            && state
+                .compare_exchange(s, s | WAITERS, Ordering::Relaxed, Ordering::Relaxed)
+                .is_err()
+        {
+            continue;
+        }
+
+        // A dead owner will never wake us up, so wake up regularly to check on it.
+        // Only then, since checking takes a few syscalls. All other outcomes
+        // (woken, changed, interrupted) mean: look again.
+        let s = s | WAITERS;
+        if futex_wait(state, s, Some(OWNER_CHECK_INTERVAL)) == Ok(WaitOutcome::TimedOut)
+            && !is_alive(s & !WAITERS)
+        {
+            // Only one waiter can win this, the others will see our tid.
+            if state
+                .compare_exchange(s, tid | WAITERS, Ordering::Acquire, Ordering::Relaxed)
+                .is_ok()
+            {
+                return true;
+            }
+        }
+    }
+}
+
+impl<T> Drop for SharedMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        let state = &self.mutex.state;
+        if state.swap(0, Ordering::Release) & WAITERS != 0 {
+            wake(state, 1).expect("FUTEX_WAKE failed");
+        }
+    }
+}
+
+/// A value in a shared memory mapping (backed by a `memfd`), which stays shared
+/// with child processes after a `fork`.
+///
+/// The value is never dropped, since all the processes would drop the same value.
+pub struct SharedMemory<T> {
+    ptr: *mut T,
+}
+
+unsafe impl<T> Send for SharedMemory<T> where T: Send {}
+unsafe impl<T> Sync for SharedMemory<T> where T: Sync {}
+
+impl<T> SharedMemory<T> {
+    pub fn new(value: T) -> io::Result<Self> {
+        let fd = unsafe { libc::memfd_create(c"shared_mutex".as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // mmap rounds up to whole pages.
+        let len = size_of::<T>().max(1);
+        let result = unsafe {
+            if libc::ftruncate(fd, len as libc::off_t) < 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                let p = libc::mmap(
+                    ptr::null_mut(),
+                    len,
+                    libc::PROT_READ | libc::PROT_WRITE,
+                    libc::MAP_SHARED,
+                    fd,
+                    0,
+                );
+                if p == libc::MAP_FAILED {
+                    Err(io::Error::last_os_error())
+                } else {
+                    // Page aligned, so aligned enough for T.
+                    let ptr = p as *mut T;
+                    ptr.write(value);
+                    Ok(Self { ptr })
+                }
+            }
+        };
+        // The mapping keeps the memory alive.
+        unsafe { libc::close(fd) };
+        result
+    }
+}
+
+impl<T> Deref for SharedMemory<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> Drop for SharedMemory<T> {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr as *mut libc::c_void, size_of::<T>().max(1)) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SharedMemory, SharedMutex, WAITERS};
+    use ch08::futex::gettid;
+    use std::{mem, sync::atomic::Ordering, thread};
+
+    /// Run `f` in a forked child process, and return its exit code.
+    /// The child must not allocate or take locks that another thread might
+    /// have held during the fork, since only the forking thread is copied.
+    fn in_child_process(f: impl FnOnce() -> i32) -> libc::pid_t {
+        let pid = unsafe { libc::fork() };
+        assert!(pid >= 0, "fork failed");
+        if pid == 0 {
+            let code = f();
+            unsafe { libc::_exit(code) };
+        }
+        pid
+    }
+
+    fn wait_for(pid: libc::pid_t) -> i32 {
+        let mut status = 0;
+        assert_eq!(unsafe { libc::waitpid(pid, &mut status, 0) }, pid);
+        assert!(libc::WIFEXITED(status));
+        libc::WEXITSTATUS(status)
+    }
+
+    #[test]
+    fn repr_c_layout() {
+        assert_eq!(mem::offset_of!(SharedMutex<u64>, state), 0);
+        assert_eq!(mem::offset_of!(SharedMutex<u64>, value), 8);
+    }
+
+    #[test]
+    fn two_processes() {
+        const N: u64 = 20_000;
+        let m = SharedMemory::new(SharedMutex::new(0u64)).unwrap();
+
+        let child = in_child_process(|| {
+            for _ in 0..N {
+                *m.lock().unwrap() += 1;
+            }
+            0
+        });
+        for _ in 0..N {
+            *m.lock().unwrap() += 1;
+        }
+        assert_eq!(wait_for(child), 0);
+
+        assert_eq!(*m.lock().unwrap(), 2 * N);
+        assert_eq!(m.state.load(Ordering::Relaxed) & !WAITERS, 0);
+    }
+
+    #[test]
+    fn dead_owner() {
+        let m = SharedMemory::new(SharedMutex::new(0u64)).unwrap();
+
+        let child = in_child_process(|| {
+            let mut g = m.lock().unwrap();
+            *g = 1;
+            // Exit while holding the lock.
+            mem::forget(g);
+            0
+        });
+        assert_eq!(wait_for(child), 0);
+        assert_eq!(m.state.load(Ordering::Relaxed), child as u32);
+
+        // We take over the lock, and are told about it.
+        let g = m.lock().unwrap_err().into_inner();
+        assert_eq!(*g, 1);
+        assert_eq!(m.state.load(Ordering::Relaxed) & !WAITERS, gettid());
+        drop(g);
+
+        // Only once.
+        assert!(m.lock().is_ok());
+    }
+
+    #[test]
+    fn dead_owner_thread() {
+        // The owner's process is still alive, but the owner thread is not.
+        let m = SharedMutex::new(0u64);
+        thread::scope(|s| {
+            s.spawn(|| mem::forget(m.lock().unwrap()));
+        });
+        assert!(m.try_lock().is_none());
+        assert!(m.lock().is_err());
+        assert!(m.lock().is_ok());
+    }
+
+    #[test]
+    fn dead_owner_while_waiting() {
+        let m = SharedMemory::new(SharedMutex::new(0u64)).unwrap();
+        let ready = SharedMemory::new(std::sync::atomic::AtomicU32::new(0)).unwrap();
+
+        let child = in_child_process(|| {
+            mem::forget(m.lock().unwrap());
+            ready.store(1, Ordering::Release);
+            // Die a little later, while the parent is blocked.
+            unsafe { libc::usleep(200_000) };
+            0
+        });
+        while ready.load(Ordering::Acquire) == 0 {
+            std::thread::yield_now();
+        }
+        assert!(m.try_lock().is_none());
+        assert!(m.lock().is_err());
+        assert_eq!(wait_for(child), 0);
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    hint::spin_loop,
+    sync::atomic::{AtomicU32, Ordering},
+    thread,
+};
+
+/// What a mutex does when it finds the lock taken, before falling back to
+/// sleeping with a syscall.
+pub trait SpinStrategy {
+    /// Wait a bit while the mutex state is 1 (locked, no other threads waiting),
+    /// in the hope that the lock is released soon.
+    /// Returns when it's time to try to lock again, or to give up and sleep.
+    fn spin(&self, state: &AtomicU32);
+}
+
+/// Spin a fixed number of times. `FixedSpin<100>` is what mutex_v3 always did.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FixedSpin<const SPINS: u32 = 100>;
+
+impl<const SPINS: u32> SpinStrategy for FixedSpin<SPINS> {
+    fn spin(&self, state: &AtomicU32) {
+        let mut spin_count = 0;
+        while state.load(Ordering::Relaxed) == 1 && spin_count < SPINS {
+            spin_count += 1;
+            spin_loop();
+        }
+    }
+}
+
+/// Check the state less and less often: after 1, 2, 4, ... spins,
+/// up to MAX_SPINS spins in total.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ExponentialBackoff<const MAX_SPINS: u32 = 1024>;
+
+impl<const MAX_SPINS: u32> ExponentialBackoff<MAX_SPINS> {
+    /// The number of spins between checks: 1, 2, 4, ..., with the last one
+    /// cut short so that they add up to exactly MAX_SPINS.
+    fn rounds() -> impl Iterator<Item = u32> {
+        let mut backoff: u32 = 1;
+        let mut spin_count = 0;
+        std::iter::from_fn(move || {
+            let round = backoff.min(MAX_SPINS - spin_count);
+            spin_count += round;
+            backoff = backoff.saturating_mul(2);
+            (round > 0).then_some(round)
+        })
+    }
+}
+
+impl<const MAX_SPINS: u32> SpinStrategy for ExponentialBackoff<MAX_SPINS> {
+    fn spin(&self, state: &AtomicU32) {
+        for round in Self::rounds() {
+            if state.load(Ordering::Relaxed) != 1 {
+                break;
+            }
+            for _ in 0..round {
+                spin_loop();
+            }
+        }
+    }
+}
+
+/// Spin SPINS times, then give the rest of the time slice to other threads
+/// up to YIELDS times. Helps when there are more threads than cores, and the
+/// lock owner may not be running at all.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct YieldAfter<const SPINS: u32 = 100, const YIELDS: u32 = 10>;
+
+impl<const SPINS: u32, const YIELDS: u32> SpinStrategy for YieldAfter<SPINS, YIELDS> {
+    fn spin(&self, state: &AtomicU32) {
+        FixedSpin::<SPINS>.spin(state);
+
+        let mut yield_count = 0;
+        while state.load(Ordering::Relaxed) == 1 && yield_count < YIELDS {
+            yield_count += 1;
+            thread::yield_now();
+        }
+    }
+}
+
+/// Learn how long the lock is usually held, and spin about that long.
+///
+/// Keeps a running average of the number of spins it took until the lock was
+/// released, and spins up to twice that. If the lock wasn't released in time,
+/// it's probably held for long periods, and the average is lowered instead.
+#[derive(Debug, Default)]
+pub struct Adaptive {
+    average: AtomicU32,
+}
+
+impl Adaptive {
+    const MAX_SPINS: u32 = 1000;
+
+    pub const fn new() -> Self {
+        Self {
+            average: AtomicU32::new(0),
+        }
+    }
+}
+
+impl SpinStrategy for Adaptive {
+    fn spin(&self, state: &AtomicU32) {
+        // Relaxed: the average is only a hint, racing updates are fine.
+        let average = self.average.load(Ordering::Relaxed);
+        let limit = (average * 2 + 10).min(Self::MAX_SPINS);
+
+        let mut spin_count = 0;
+        while state.load(Ordering::Relaxed) == 1 && spin_count < limit {
+            spin_count += 1;
+            spin_loop();
+        }
+
+        // Move the average 1/8 of the way toward what we observed.
+        let new_average = if spin_count < limit {
+            average + spin_count / 8 - average / 8
+        } else {
+            average - average / 8
+        };
+        self.average.store(new_average, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Adaptive, ExponentialBackoff, SpinStrategy};
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[test]
+    fn exponential_backoff_spins_at_most_max_spins() {
+        let rounds: Vec<u32> = ExponentialBackoff::<1024>::rounds().collect();
+        assert_eq!(rounds, [1, 2, 4, 8, 16, 32, 64, 128, 256, 512, 1]);
+        assert_eq!(rounds.iter().sum::<u32>(), 1024);
+
+        let rounds: Vec<u32> = ExponentialBackoff::<10>::rounds().collect();
+        assert_eq!(rounds, [1, 2, 4, 3]);
+
+        assert_eq!(ExponentialBackoff::<0>::rounds().count(), 0);
+        assert_eq!(
+            ExponentialBackoff::<{ u32::MAX }>::rounds().sum::<u32>(),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn adaptive_backs_off_when_lock_is_held_for_long() {
+        let spin = Adaptive::new();
+        spin.average.store(400, Ordering::Relaxed);
+
+        // Never released while spinning
+        let state = AtomicU32::new(1);
+        for _ in 0..100 {
+            spin.spin(&state);
+        }
+        assert!(spin.average.load(Ordering::Relaxed) < 10);
+    }
+
+    #[test]
+    fn adaptive_stops_immediately_when_unlocked() {
+        let spin = Adaptive::new();
+        let state = AtomicU32::new(0);
+        spin.spin(&state);
+        assert_eq!(spin.average.load(Ordering::Relaxed), 0);
+    }
+}
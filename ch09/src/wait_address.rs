@@ -0,0 +1,214 @@
+//! The backends behind the wait and wake operations of all primitives in this crate.
+//!
+//! By default, that's the `Futex` backend. With the `portable-wait` feature, it's
+//! the `ParkingLot` backend instead, which only uses `std::thread::park`, so the
+//! primitives can be tested against both on the same machine:
+//!
+//! ```text
+//! cargo test -p ch09
+//! cargo test -p ch09 --features portable-wait
+//! ```
+//!
+//! (`PiMutex` and `SharedMutex` always use futexes directly, since they need the kernel.)
+
+use std::{
+    sync::atomic::{AtomicU32, Ordering},
+    time::Duration,
+};
+
+use crate::parking_lot;
+
+/// Blocking on the value of an `AtomicU32`, like a futex.
+pub trait WaitAddress {
+    /// Block as long as `a` contains `expected`, until woken up by `wake_one` or
+    /// `wake_all` on the same atomic, or until the `timeout` expires.
+    ///
+    /// May also return spuriously, so the caller should check the atomic again.
+    fn wait(a: &AtomicU32, expected: u32, timeout: Option<Duration>);
+    /// Wake up one thread waiting on `a`, if any.
+    fn wake_one(a: &AtomicU32);
+    /// Wake up all threads waiting on `a`.
+    fn wake_all(a: &AtomicU32);
+}
+
+#[cfg(not(feature = "portable-wait"))]
+pub type Backend = Futex;
+#[cfg(feature = "portable-wait")]
+pub type Backend = ParkingLot;
+
+/// The operating system's futex (or equivalent): on Linux the private futex
+/// operations of ch08, elsewhere `atomic_wait`.
+///
+/// `atomic_wait` has no timeout, so off Linux, timed waits use the `ParkingLot`
+/// backend instead, and the wake operations wake threads in both.
+pub struct Futex;
+
+#[cfg(target_os = "linux")]
+impl WaitAddress for Futex {
+    fn wait(a: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        // All outcomes (woken, timed out, value mismatch, interrupted) are fine:
+        // the caller checks the atomic again.
+        let _ = ch08::futex::futex_wait_private(a, expected, timeout);
+    }
+
+    fn wake_one(a: &AtomicU32) {
+        let _ = ch08::futex::wake_private(a, 1);
+    }
+
+    fn wake_all(a: &AtomicU32) {
+        let _ = ch08::futex::wake_all_private(a);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+impl WaitAddress for Futex {
+    fn wait(a: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        match timeout {
+            None => atomic_wait::wait(a, expected),
+            Some(_) => ParkingLot::wait(a, expected, timeout),
+        }
+    }
+
+    // This might wake up two threads, one in each, but that's just a spurious
+    // wake-up for one of them.
+    fn wake_one(a: &AtomicU32) {
+        atomic_wait::wake_one(a);
+        ParkingLot::wake_one(a);
+    }
+
+    fn wake_all(a: &AtomicU32) {
+        atomic_wait::wake_all(a);
+        ParkingLot::wake_all(a);
+    }
+}
+
+/// A portable backend on top of the `parking_lot` module: a global hash table,
+/// keyed by the address of the atomic, of queues of threads blocked with
+/// `std::thread::park`.
+pub struct ParkingLot;
+
+impl WaitAddress for ParkingLot {
+    fn wait(a: &AtomicU32, expected: u32, timeout: Option<Duration>) {
+        // The value is checked while holding the queue lock: a wake operation
+        // takes the same lock after changing the value, so it either happens
+        // before our check, or finds us in the queue. No lost wake-ups.
+        parking_lot::park(
+            a as *const AtomicU32 as usize,
+            || a.load(Ordering::Relaxed) == expected,
+            timeout,
+        );
+    }
+
+    fn wake_one(a: &AtomicU32) {
+        parking_lot::unpark_one(a as *const AtomicU32 as usize, |_| {});
+    }
+
+    fn wake_all(a: &AtomicU32) {
+        parking_lot::unpark_all(a as *const AtomicU32 as usize, |_| {});
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Futex, ParkingLot, WaitAddress};
+    use std::{
+        sync::atomic::{AtomicU32, Ordering},
+        thread,
+        time::{Duration, Instant},
+    };
+
+    // Each test runs against both backends.
+
+    fn value_mismatch<B: WaitAddress>() {
+        let a = AtomicU32::new(0);
+        B::wait(&a, 1, None); // Returns immediately
+    }
+
+    fn timeout<B: WaitAddress>() {
+        let a = AtomicU32::new(0);
+        let start = Instant::now();
+        // May return spuriously, so loop until the deadline.
+        while start.elapsed() < Duration::from_millis(20) {
+            B::wait(&a, 0, Some(Duration::from_millis(20)));
+        }
+    }
+
+    fn wake_one_at_a_time<B: WaitAddress>() {
+        let a = AtomicU32::new(0);
+        let woken = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while a.load(Ordering::Acquire) == 0 {
+                        B::wait(&a, 0, None);
+                    }
+                    woken.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            a.store(1, Ordering::Release);
+            // Keep waking one at a time until everybody is out.
+            while woken.load(Ordering::Relaxed) < 4 {
+                B::wake_one(&a);
+                thread::yield_now();
+            }
+        });
+    }
+
+    fn wake_all_wakes_everybody<B: WaitAddress>() {
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| {
+                    while a.load(Ordering::Acquire) == 0 {
+                        B::wait(&a, 0, None);
+                    }
+                });
+            }
+            thread::sleep(Duration::from_millis(10));
+            a.store(1, Ordering::Release);
+            B::wake_all(&a);
+        });
+    }
+
+    fn ping_pong<B: WaitAddress>() {
+        // No lost wake-ups: each side waits for the other's parity.
+        let a = AtomicU32::new(0);
+        thread::scope(|s| {
+            for parity in 0..2 {
+                let a = &a;
+                s.spawn(move || loop {
+                    let v = a.load(Ordering::Acquire);
+                    if v >= 10_000 {
+                        break;
+                    }
+                    if v % 2 == parity {
+                        a.store(v + 1, Ordering::Release);
+                        B::wake_one(a);
+                    } else {
+                        B::wait(a, v, None);
+                    }
+                });
+            }
+        });
+    }
+
+    macro_rules! backend_tests {
+        ($($name:ident),*) => {
+            mod futex {
+                $(#[test] fn $name() { super::$name::<super::Futex>() })*
+            }
+            mod parking_lot {
+                $(#[test] fn $name() { super::$name::<super::ParkingLot>() })*
+            }
+        };
+    }
+
+    backend_tests!(
+        value_mismatch,
+        timeout,
+        wake_one_at_a_time,
+        wake_all_wakes_everybody,
+        ping_pong
+    );
+}